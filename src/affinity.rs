@@ -0,0 +1,217 @@
+use crate::{numa, Error};
+use log::warn;
+use std::{collections::BTreeSet, fs, io, path::Path, str::FromStr};
+
+pub const SYSFS_ROOT: &str = "/sys";
+
+/// Which CPUs the mining threads are pinned to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuAffinity {
+    /// One thread per physical core, skipping SMT siblings and alternating between NUMA nodes
+    Auto,
+    Cpus(Vec<usize>),
+}
+
+impl FromStr for CpuAffinity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Auto);
+        }
+        let cpus = parse_cpu_list(s).map_err(|_| {
+            format!("cpu-affinity should be `auto` or a list of cpus/ranges e.g. 0-3,8,10-11, got: {}", s)
+        })?;
+        if cpus.is_empty() {
+            return Err("cpu-affinity should contain at least a single cpu".into());
+        }
+        Ok(Self::Cpus(cpus))
+    }
+}
+
+impl CpuAffinity {
+    /// The CPUs to pin the mining threads to, in the order threads are assigned to them
+    pub fn cpus(&self, sys_root: &Path) -> Result<Vec<usize>, Error> {
+        let cpus = match self {
+            Self::Cpus(cpus) => cpus.clone(),
+            Self::Auto => {
+                let cores = physical_cores(sys_root)?;
+                match numa::numa_nodes(sys_root) {
+                    Ok(nodes) => numa::interleave(&nodes, &cores),
+                    Err(_) => cores,
+                }
+            }
+        };
+        if let Ok(allowed) = current_affinity() {
+            let outside: Vec<_> = cpus.iter().filter(|cpu| !allowed.contains(cpu)).collect();
+            if !outside.is_empty() {
+                warn!("CPUs {:?} are outside of the CPUs this process is allowed to run on ({:?})", outside, allowed);
+            }
+        }
+        Ok(cpus)
+    }
+}
+
+/// Parses the kernel's cpu list format, e.g. `0-3,8,10-11`. Returns the cpus sorted and deduplicated.
+pub fn parse_cpu_list(s: &str) -> Result<Vec<usize>, Error> {
+    let mut cpus = BTreeSet::new();
+    for part in s.trim().split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end): (usize, usize) = (start.trim().parse()?, end.trim().parse()?);
+                if start > end {
+                    return Err(format!("Invalid cpu range: {}", part).into());
+                }
+                cpus.extend(start..=end);
+            }
+            None => {
+                cpus.insert(part.parse()?);
+            }
+        }
+    }
+    Ok(cpus.into_iter().collect())
+}
+
+/// The first logical CPU of every online physical core, according to `sys_root/devices/system/cpu`
+pub fn physical_cores(sys_root: &Path) -> Result<Vec<usize>, Error> {
+    let cpu_dir = sys_root.join("devices/system/cpu");
+    let online = match fs::read_to_string(cpu_dir.join("online")) {
+        Ok(online) => parse_cpu_list(&online)?,
+        Err(_) => {
+            let mut cpus = Vec::new();
+            for entry in fs::read_dir(&cpu_dir)? {
+                let name = entry?.file_name();
+                if let Some(Ok(cpu)) = name.to_str().and_then(|name| name.strip_prefix("cpu")).map(str::parse) {
+                    cpus.push(cpu);
+                }
+            }
+            cpus.sort_unstable();
+            cpus
+        }
+    };
+    let mut cores = Vec::new();
+    for cpu in online {
+        let siblings_path = cpu_dir.join(format!("cpu{}/topology/thread_siblings_list", cpu));
+        let first_sibling = match fs::read_to_string(siblings_path) {
+            Ok(siblings) => parse_cpu_list(&siblings)?.first().copied().unwrap_or(cpu),
+            // No topology information, so treat it as a core of its own
+            Err(_) => cpu,
+        };
+        if first_sibling == cpu {
+            cores.push(cpu);
+        }
+    }
+    if cores.is_empty() {
+        return Err(format!("Couldn't find any online cpus in {}", cpu_dir.display()).into());
+    }
+    Ok(cores)
+}
+
+/// Pins the calling thread to a single CPU
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpu: usize) -> io::Result<()> {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("CPU {} is out of range", cpu)));
+    }
+    // SAFETY: `cpu_set_t` is a plain bitmask, so all zeros is a valid empty set, and `cpu` was bounds checked above.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpu: usize) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "CPU affinity is only supported on Linux"))
+}
+
+/// The CPUs the calling thread is allowed to run on
+#[cfg(target_os = "linux")]
+pub fn current_affinity() -> io::Result<Vec<usize>> {
+    // SAFETY: `cpu_set_t` is a plain bitmask, and the kernel writes at most `size_of::<cpu_set_t>()` bytes into it.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((0..libc::CPU_SETSIZE as usize).filter(|&cpu| libc::CPU_ISSET(cpu, &set)).collect())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn current_affinity() -> io::Result<Vec<usize>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "CPU affinity is only supported on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0-3,8,10-11").unwrap(), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_cpu_list("5, 1,1-2\n").unwrap(), vec![1, 2, 5]);
+        assert_eq!(parse_cpu_list("").unwrap(), Vec::<usize>::new());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+        assert!(parse_cpu_list("1-").is_err());
+
+        assert_eq!("auto".parse(), Ok(CpuAffinity::Auto));
+        assert_eq!("2,4-5".parse(), Ok(CpuAffinity::Cpus(vec![2, 4, 5])));
+        assert!(",".parse::<CpuAffinity>().is_err());
+    }
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path: PathBuf = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_physical_cores() {
+        // 4 cores with 2 threads each, where the siblings are numbered `n` and `n + 4`, and core 3 is offline
+        let root = TempDir::new().unwrap();
+        write(root.path(), "devices/system/cpu/online", "0-2,4-6\n");
+        for cpu in 0..8 {
+            let siblings = format!("{},{}\n", cpu % 4, cpu % 4 + 4);
+            write(root.path(), &format!("devices/system/cpu/cpu{}/topology/thread_siblings_list", cpu), &siblings);
+        }
+        assert_eq!(physical_cores(root.path()).unwrap(), vec![0, 1, 2]);
+        assert_eq!(CpuAffinity::Auto.cpus(root.path()).unwrap(), vec![0, 1, 2]);
+        // With two NUMA nodes the cores alternate between them
+        write(root.path(), "devices/system/node/node0/cpulist", "0-1,4-5\n");
+        write(root.path(), "devices/system/node/node1/cpulist", "2-3,6-7\n");
+        assert_eq!(CpuAffinity::Auto.cpus(root.path()).unwrap(), vec![0, 2, 1]);
+
+        // Without an `online` file or topology every cpu directory is a core
+        let root = TempDir::new().unwrap();
+        for cpu in [0, 1, 10] {
+            fs::create_dir_all(root.path().join(format!("devices/system/cpu/cpu{}", cpu))).unwrap();
+        }
+        fs::create_dir_all(root.path().join("devices/system/cpu/cpufreq")).unwrap();
+        assert_eq!(physical_cores(root.path()).unwrap(), vec![0, 1, 10]);
+
+        assert!(physical_cores(TempDir::new().unwrap().path()).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pin_current_thread() {
+        let allowed = current_affinity().unwrap();
+        let cpu = *allowed.last().unwrap();
+        let pinned = std::thread::spawn(move || {
+            pin_current_thread(cpu).unwrap();
+            current_affinity().unwrap()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(pinned, vec![cpu]);
+        assert!(pin_current_thread(usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{
+    cli::BenchOpt,
+    miner::{MinerConfig, MinerManager},
+    proto::{RpcBlock, RpcBlockHeader},
+    Error, ShutdownHandler,
+};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicBool, Arc},
+    time::{Duration, Instant},
+};
+
+// Time for the threads to start and get the template before measuring
+const WARMUP: Duration = Duration::from_secs(1);
+// Thread counts within this fraction of the best hashrate are considered as good, and the smallest one is recommended
+const RECOMMEND_TOLERANCE: f64 = 0.02;
+
+/// Where `bench --save` stores the recommended thread count, unless overridden with `--tuning-file`
+pub fn tuning_file(path: Option<&Path>) -> Option<PathBuf> {
+    if let Some(path) = path {
+        return Some(path.to_owned());
+    }
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("vecno-cpu-miner").join("threads"))
+}
+
+/// The thread count saved by `bench --save`, if there is one
+pub fn saved_threads(path: &Path) -> Result<Option<u16>, Error> {
+    match fs::read_to_string(path) {
+        Ok(threads) => Ok(Some(threads.trim().parse()?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn save_threads(path: &Path, threads: u16) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, format!("{}\n", threads))?;
+    Ok(())
+}
+
+/// A template that is mined like a real one, with a target of the compact `bits`.
+/// Shared with the tests and the benches.
+pub(crate) fn synthetic_block(bits: u32) -> RpcBlock {
+    RpcBlock {
+        header: Some(RpcBlockHeader {
+            version: 1,
+            parents: vec![],
+            hash_merkle_root: "23618af45051560529440541e7dc56be27676d278b1e00324b048d410a19d764".to_string(),
+            accepted_id_merkle_root: "947d1a10378d6478b6957a0ed71866812dee33684968031b1cace4908c149d94".to_string(),
+            utxo_commitment: "ec5e8fc0bc0c637004cee262cef12e7cf6d9cd7772513dbd466176a07ab7c4f4".to_string(),
+            timestamp: 654654353,
+            bits,
+            nonce: 0,
+            daa_score: 654456,
+            blue_work: "d8e28a03234786".to_string(),
+            pruning_point: "be4c415d378f9113fabd3c09fcc84ddb6a00f900c87cb6a1186993ddc3014e2d".to_string(),
+            blue_score: 1164419,
+        }),
+        transactions: vec![],
+        verbose_data: None,
+    }
+}
+
+/// Mines the synthetic template with a number of threads and returns the hashrate
+async fn measure(threads: u16, duration: Duration) -> Result<f64, Error> {
+    let shutdown = ShutdownHandler(Arc::new(AtomicBool::new(false)));
+    let _shutdown_when_dropped = shutdown.arm();
+    let config = MinerConfig { n_cpus: Some(threads), quiet: true, ..Default::default() };
+    let mut manager = MinerManager::new(config, shutdown);
+    let stats = manager.stats();
+    // A target low enough to practically never find a block
+    manager.process_block(0, Some(synthetic_block(0x1a00ffff)))?;
+    tokio::time::sleep(WARMUP).await;
+
+    stats.take_thread_hashes();
+    stats.take_retired_hashes();
+    let start = Instant::now();
+    tokio::time::sleep(duration).await;
+    let hashes =
+        stats.take_thread_hashes().iter().map(|&(_, hashes)| hashes).sum::<u64>() + stats.take_retired_hashes();
+    Ok(hashes as f64 / start.elapsed().as_secs_f64())
+}
+
+/// Hashrate per thread compared to a single thread
+fn efficiency(single: f64, threads: u16, rate: f64) -> f64 {
+    rate / (single * f64::from(threads))
+}
+
+/// The fewest threads that get close to the best hashrate
+fn recommend(results: &[(u16, f64)]) -> Option<u16> {
+    let best = results.iter().map(|&(_, rate)| rate).fold(0.0, f64::max);
+    results.iter().filter(|&&(_, rate)| rate >= best * (1.0 - RECOMMEND_TOLERANCE)).map(|&(threads, _)| threads).min()
+}
+
+pub async fn run(opt: &BenchOpt, tuning_file: Option<&Path>) -> Result<(), Error> {
+    let logical = num_cpus::get();
+    let max_threads = opt.max_threads.unwrap_or_else(|| logical.try_into().unwrap_or(u16::MAX));
+    let duration = Duration::from_secs(opt.duration);
+    println!("Physical cores: {}, logical CPUs: {}", num_cpus::get_physical(), logical);
+    println!("Mining a synthetic template for {}s with 1 to {} threads", opt.duration, max_threads);
+
+    let mut results = Vec::new();
+    for threads in 1..=max_threads {
+        let rate = measure(threads, duration).await?;
+        results.push((threads, rate));
+        let (rate_display, suffix) = MinerManager::hash_suffix(rate);
+        let efficiency = efficiency(results[0].1, threads, rate);
+        println!(
+            "{:>3} threads: {:>8.2} {:<8} scaling efficiency {:>5.1}%",
+            threads,
+            rate_display,
+            suffix,
+            efficiency * 100.0
+        );
+    }
+
+    let recommended = recommend(&results).ok_or("No thread count managed to mine")?;
+    println!("Recommended: --threads {}", recommended);
+    if opt.save {
+        let path = tuning_file.ok_or("Couldn't find a config directory, pass --tuning-file")?;
+        save_threads(path, recommended)?;
+        println!("Saved to {}, it will be used when --threads isn't passed", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_recommend() {
+        assert_eq!(recommend(&[]), None);
+        assert_eq!(recommend(&[(1, 100.0), (2, 190.0), (3, 250.0), (4, 240.0)]), Some(3));
+        // Extra threads that barely help aren't worth the power
+        assert_eq!(recommend(&[(1, 100.0), (2, 200.0), (3, 201.0), (4, 203.0)]), Some(2));
+        assert_eq!(efficiency(100.0, 4, 300.0), 0.75);
+    }
+
+    #[test]
+    fn test_saved_threads() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("vecno-cpu-miner/threads");
+        assert_eq!(saved_threads(&path).unwrap(), None);
+        save_threads(&path, 12).unwrap();
+        assert_eq!(saved_threads(&path).unwrap(), Some(12));
+        fs::write(&path, "many").unwrap();
+        assert!(saved_threads(&path).is_err());
+        assert_eq!(tuning_file(Some(&path)), Some(path));
+    }
+
+    // The mining threads need real threads, which shuttle's primitives don't support outside of its scheduler
+    #[cfg(not(feature = "shuttle"))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_measure() {
+        assert!(measure(1, Duration::from_millis(200)).await.unwrap() > 0.0);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::{affinity, Error};
+use log::{info, warn};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Parses cgroup v2's `cpu.max`, e.g. `200000 100000` is 2 CPUs and `max 100000` is unlimited
+fn parse_cpu_max(cpu_max: &str) -> Result<Option<f64>, Error> {
+    let mut fields = cpu_max.split_whitespace();
+    let (quota, period) = (fields.next().ok_or("Empty cpu.max")?, fields.next().unwrap_or("100000"));
+    if quota == "max" {
+        return Ok(None);
+    }
+    Ok(Some(quota.parse::<f64>()? / period.parse::<f64>()?))
+}
+
+/// Reads cgroup v1's `cpu.cfs_quota_us` and `cpu.cfs_period_us`, where a quota of -1 is unlimited
+fn read_cfs_quota(dir: &Path) -> Result<Option<f64>, Error> {
+    let quota: i64 = fs::read_to_string(dir.join("cpu.cfs_quota_us"))?.trim().parse()?;
+    if quota < 0 {
+        return Ok(None);
+    }
+    let period: i64 = fs::read_to_string(dir.join("cpu.cfs_period_us"))?.trim().parse()?;
+    Ok(Some(quota as f64 / period as f64))
+}
+
+/// The directory of the cgroup and all of its parents up to `root`, as limits of parents apply too
+fn cgroup_dirs(root: &Path, cgroup: &str) -> Vec<PathBuf> {
+    let mut dirs = vec![root.to_owned()];
+    let mut dir = root.to_owned();
+    for part in cgroup.split('/').filter(|part| !part.is_empty()) {
+        dir.push(part);
+        dirs.push(dir.clone());
+    }
+    dirs
+}
+
+/// The CPU quota of this process' cgroup in CPUs, `None` when unlimited.
+/// Looks the cgroup up in `proc_root/self/cgroup` and supports both cgroup v1 and v2.
+pub fn cpu_quota(cgroup_root: &Path, proc_root: &Path) -> Result<Option<f64>, Error> {
+    let cgroups = fs::read_to_string(proc_root.join("self/cgroup"))?;
+    let mut quota: Option<f64> = None;
+    let mut min_quota = |limit: Option<f64>| {
+        if let Some(limit) = limit {
+            quota = Some(quota.map_or(limit, |quota| quota.min(limit)));
+        }
+    };
+    for line in cgroups.lines() {
+        // hierarchy-ID:controller-list:cgroup-path
+        let mut fields = line.splitn(3, ':');
+        let (Some(_), Some(controllers), Some(cgroup)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        if controllers.is_empty() {
+            for dir in cgroup_dirs(cgroup_root, cgroup) {
+                if let Ok(cpu_max) = fs::read_to_string(dir.join("cpu.max")) {
+                    min_quota(parse_cpu_max(&cpu_max)?);
+                }
+            }
+        } else if controllers.split(',').any(|controller| controller == "cpu") {
+            let Some(mount) =
+                [controllers, "cpu", "cpu,cpuacct"].iter().map(|name| cgroup_root.join(name)).find(|dir| dir.is_dir())
+            else {
+                continue;
+            };
+            for dir in cgroup_dirs(&mount, cgroup) {
+                if dir.join("cpu.cfs_quota_us").exists() {
+                    min_quota(read_cfs_quota(&dir)?);
+                }
+            }
+        }
+    }
+    Ok(quota)
+}
+
+/// What limits the CPUs available to the miner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuBudget {
+    pub physical_cores: usize,
+    /// CPUs in the affinity mask, `None` if it couldn't be read
+    pub allowed_cpus: Option<usize>,
+    /// CPU quota of the cgroup, `None` when unlimited
+    pub quota: Option<f64>,
+}
+
+impl CpuBudget {
+    pub fn read(cgroup_root: &Path, proc_root: &Path) -> Self {
+        let quota = cpu_quota(cgroup_root, proc_root).unwrap_or_else(|e| {
+            warn!("Failed reading the cgroup CPU quota: {}", e);
+            None
+        });
+        Self {
+            physical_cores: num_cpus::get_physical(),
+            allowed_cpus: affinity::current_affinity().ok().map(|cpus| cpus.len()),
+            quota,
+        }
+    }
+
+    /// A thread per physical core, but no more than the CPUs the process may run on and its quota allows,
+    /// rounded down so the threads don't get throttled
+    pub fn threads(&self) -> u16 {
+        let quota = self.quota.map(|quota| quota.floor() as usize);
+        let threads = [Some(self.physical_cores), self.allowed_cpus, quota].into_iter().flatten().min().unwrap_or(1);
+        threads.clamp(1, u16::MAX.into()) as u16
+    }
+
+    /// The default thread count, logging how it was decided
+    pub fn default_threads(cgroup_root: &Path, proc_root: &Path) -> u16 {
+        let budget = Self::read(cgroup_root, proc_root);
+        let threads = budget.threads();
+        let allowed = budget.allowed_cpus.map_or_else(|| "unknown".to_owned(), |cpus| cpus.to_string());
+        let quota = budget.quota.map_or_else(|| "unlimited".to_owned(), |quota| format!("{:.2} CPUs", quota));
+        info!(
+            "Defaulting to {} threads: {} physical cores, {} CPUs in the affinity mask, cgroup quota {}",
+            threads, budget.physical_cores, allowed, quota
+        );
+        threads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_cgroup_v2_quota() {
+        let root = TempDir::new().unwrap();
+        let (cgroup, proc) = (root.path().join("cgroup"), root.path().join("proc"));
+        write(&proc, "self/cgroup", "0::/kubepods/pod1/miner\n");
+        assert_eq!(cpu_quota(&cgroup, &proc).unwrap(), None);
+
+        write(&cgroup, "cpu.max", "max 100000\n");
+        write(&cgroup, "kubepods/pod1/cpu.max", "350000 100000\n");
+        write(&cgroup, "kubepods/pod1/miner/cpu.max", "max 100000\n");
+        assert_eq!(cpu_quota(&cgroup, &proc).unwrap(), Some(3.5));
+
+        // Inside a cgroup namespace the process sees its own cgroup as the root
+        write(&proc, "self/cgroup", "0::/\n");
+        write(&cgroup, "cpu.max", "150000 100000\n");
+        assert_eq!(cpu_quota(&cgroup, &proc).unwrap(), Some(1.5));
+
+        write(&cgroup, "cpu.max", "lots\n");
+        assert!(cpu_quota(&cgroup, &proc).is_err());
+    }
+
+    #[test]
+    fn test_cgroup_v1_quota() {
+        let root = TempDir::new().unwrap();
+        let (cgroup, proc) = (root.path().join("cgroup"), root.path().join("proc"));
+        write(&proc, "self/cgroup", "12:memory:/docker/abc\n4:cpu,cpuacct:/docker/abc\n1:name=systemd:/docker/abc\n");
+        write(&cgroup, "cpu,cpuacct/cpu.cfs_quota_us", "-1\n");
+        write(&cgroup, "cpu,cpuacct/cpu.cfs_period_us", "100000\n");
+        assert_eq!(cpu_quota(&cgroup, &proc).unwrap(), None);
+
+        write(&cgroup, "cpu,cpuacct/docker/abc/cpu.cfs_quota_us", "200000\n");
+        write(&cgroup, "cpu,cpuacct/docker/abc/cpu.cfs_period_us", "100000\n");
+        assert_eq!(cpu_quota(&cgroup, &proc).unwrap(), Some(2.0));
+
+        assert!(cpu_quota(&cgroup, &root.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_cpu_budget_threads() {
+        let budget = CpuBudget { physical_cores: 16, allowed_cpus: Some(32), quota: None };
+        assert_eq!(budget.threads(), 16);
+        assert_eq!(CpuBudget { allowed_cpus: Some(4), ..budget }.threads(), 4);
+        assert_eq!(CpuBudget { quota: Some(2.5), ..budget }.threads(), 2);
+        assert_eq!(CpuBudget { allowed_cpus: None, quota: Some(0.5), ..budget }.threads(), 1);
+    }
+}
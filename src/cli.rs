@@ -1,30 +1,41 @@
-use crate::Error;
-use clap::{ArgGroup, Parser};
+use crate::{
+    affinity::CpuAffinity, governor::LimitAction, groups::ThreadGroup, idle::IdleAction, priority::Priority,
+    processes::ProcessPattern, schedule::Schedule, stale::StalePolicy, Error,
+};
+use clap::{ArgGroup, Args, Parser, Subcommand};
 use log::LevelFilter;
-use std::{net::IpAddr, str::FromStr};
+use std::{net::IpAddr, path::PathBuf, str::FromStr};
 
 #[derive(Debug, Parser)]
 #[clap(about, version, author)]
 #[clap(group(ArgGroup::new("required")))]
+#[clap(subcommand_negates_reqs = true)]
 pub struct Opt {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
     #[clap(short, long, display_order = 3)]
     /// Enable debug logging level
     pub debug: bool,
-    #[clap(short = 'a', long = "mining-address", display_order = 0)]
+    #[clap(short = 'a', long = "mining-address", required = true, display_order = 0)]
     /// The Vecno address for the miner reward
-    pub mining_address: String,
+    pub mining_address: Option<String>,
     #[clap(short = 's', long = "vecnod-address", default_value = "127.0.0.1", display_order = 1)]
     /// The IP of the vecnod instance
     pub vecnod_address: String,
 
     #[clap(long = "devfund", display_order = 6)]
-    /// Mine a percentage of the blocks to the Vecno devfund [default: Off]
+    /// Mine a percentage of the hashes to the Vecno devfund [default: Off]
     pub devfund_address: Option<String>,
 
     #[clap(long = "devfund-percent", default_value = "1", display_order = 7, value_parser = parse_devfund_percent)]
-    /// The percentage of blocks to send to the devfund
+    /// The percentage of hashes to mine to the devfund
     pub devfund_percent: u16,
 
+    #[clap(long = "group", display_order = 28)]
+    /// Mine a percentage of the hashes to another address, as ADDRESS=PERCENT. Can be repeated,
+    /// the mining address gets the rest
+    pub groups: Vec<ThreadGroup>,
+
     #[clap(short, long, display_order = 2)]
     /// Vecnod port [default: Mainnet = 7110, Testnet = 7210]
     port: Option<u16>,
@@ -33,17 +44,182 @@ pub struct Opt {
     /// Use testnet instead of mainnet [default: false]
     testnet: bool,
     #[clap(short = 't', long = "threads", display_order = 5)]
-    /// Amount of miner threads to launch [default: number of logical cpus]
+    /// Amount of miner threads to launch [default: the count saved by `bench --save`, or one per physical core
+    /// within the CPU affinity mask and cgroup CPU quota]
     pub num_threads: Option<u16>,
     #[clap(long = "mine-when-not-synced", display_order = 8)]
     /// Mine even when vecnod says it is not synced, only useful when passing `--allow-submit-block-when-not-synced` to vecnod  [default: false]
     pub mine_when_not_synced: bool,
     #[clap(long = "throttle", display_order = 9)]
-    /// Throttle (milliseconds) between each pow hash generation (used for development testing)
+    /// Throttle (milliseconds) between each pow hash generation, averaged over the work periods like `--max-cpu-percent` (used for development testing)
     pub throttle: Option<u64>,
+    #[clap(long = "max-cpu-percent", display_order = 9, value_parser = parse_cpu_percent)]
+    /// Limit every miner thread to a percentage of CPU time, by alternating between mining and resting
+    pub max_cpu_percent: Option<f64>,
+    #[clap(long = "max-hashrate", display_order = 9, value_parser = parse_hashrate)]
+    /// Cap the total hashrate (hash/s, accepts K/M/G/T suffixes e.g. 1.5M)
+    pub max_hashrate: Option<f64>,
     #[clap(long, display_order = 10)]
     /// Output logs in alternative format (same as vecnod)
     pub altlogs: bool,
+    #[clap(long = "cpu-affinity", display_order = 11)]
+    /// Pin the miner threads to CPUs, either `auto` (one thread per physical core, spread evenly over NUMA nodes)
+    /// or a list like 0-3,8,10-11. Only `auto` spreads the threads over NUMA nodes, a list is used as given
+    /// [default: no pinning]
+    pub cpu_affinity: Option<CpuAffinity>,
+    #[clap(long, display_order = 12)]
+    /// Scheduling priority of the miner threads, either `idle` (SCHED_IDLE) or a nice value between -20 and 19.
+    /// Network and block submission keep the normal priority [default: normal priority]
+    pub priority: Option<Priority>,
+    #[clap(long = "idle-aware", display_order = 13)]
+    /// Only mine on CPUs other workloads don't need, based on the CPU usage of other processes in /proc.
+    /// The load average isn't used, as it keeps counting the miner's own threads for minutes after they stop
+    pub idle_aware: bool,
+    #[clap(long = "idle-action", default_value = "shrink", display_order = 14)]
+    /// With --idle-aware, either `pause` mining or `shrink` it to the CPUs left over when the machine is busy
+    pub idle_action: IdleAction,
+    #[clap(long = "idle-threshold", default_value = "0.5", display_order = 15, value_parser = parse_idle_threshold)]
+    /// With --idle-aware, how many CPUs other workloads may use before mining backs off
+    pub idle_threshold: f64,
+    #[clap(long = "idle-quiet-period", default_value = "60", display_order = 16)]
+    /// With --idle-aware, seconds the machine has to stay quiet before mining resumes
+    pub idle_quiet_period: u64,
+    #[clap(long = "max-temp", display_order = 17, value_parser = parse_temperature)]
+    /// Park miner threads one by one while the hottest thermal zone is at or above this temperature (°C)
+    /// [default: no limit]
+    pub max_temp: Option<f64>,
+    #[clap(long = "temp-hysteresis", default_value = "5", display_order = 18, value_parser = parse_hysteresis)]
+    /// With --max-temp, how many °C below the limit the temperature has to drop before threads are resumed
+    pub temp_hysteresis: f64,
+    #[clap(long, display_order = 19)]
+    /// Only mine during these windows of local time, e.g. "weekdays 19:00-07:00, weekends all day".
+    /// Outside of them the threads are paused but the connection to vecnod is kept [default: always]
+    pub schedule: Option<Schedule>,
+    #[clap(long = "stale-policy", default_value = "submit", display_order = 20)]
+    /// What to do with a block found on a template that was already replaced: `submit` it anyway, `drop` it,
+    /// or submit it only if the template was replaced at most this long ago, e.g. 500ms or 2s
+    pub stale_policy: StalePolicy,
+    #[clap(long = "on-battery", display_order = 21)]
+    /// While running on battery, either `pause` mining or limit it to a number of threads
+    /// [default: keep mining]
+    pub on_battery: Option<LimitAction>,
+    #[clap(long = "min-battery", display_order = 22, value_parser = clap::value_parser!(u8).range(1..=100))]
+    /// Pause mining while on battery with less than this percentage of charge left [default: no limit]
+    pub min_battery: Option<u8>,
+    #[clap(long = "pause-while", value_delimiter = ',', display_order = 23)]
+    /// Back off while any of these processes runs, as a comma separated list of names from /proc/*/comm
+    /// where `*` and `?` are wildcards, e.g. "cc1*,rustc,steam,zoom"
+    pub pause_while: Vec<ProcessPattern>,
+    #[clap(long = "process-action", default_value = "pause", display_order = 24)]
+    /// With --pause-while, either `pause` mining or limit it to a number of threads
+    pub process_action: LimitAction,
+    #[clap(long = "dry-run", display_order = 25)]
+    /// Mine against vecnod as usual, but only log found blocks instead of submitting them
+    pub dry_run: bool,
+    #[clap(long = "dry-run-file", requires = "dry_run", display_order = 26)]
+    /// With --dry-run, also append the found blocks to this file
+    pub dry_run_file: Option<PathBuf>,
+    #[clap(long = "proc-root", default_value = crate::idle::PROC_ROOT, hide = true)]
+    /// Where procfs is mounted, for testing
+    pub proc_root: PathBuf,
+    #[clap(long = "sys-root", default_value = crate::affinity::SYSFS_ROOT, hide = true)]
+    /// Where sysfs is mounted, for testing
+    pub sys_root: PathBuf,
+    #[clap(long = "cgroup-root", default_value = crate::cgroup::CGROUP_ROOT, hide = true)]
+    /// Where the cgroup filesystem is mounted, for testing
+    pub cgroup_root: PathBuf,
+    #[clap(long = "tuning-file", global = true, display_order = 27)]
+    /// Where `bench --save` stores the recommended thread count [default: ~/.config/vecno-cpu-miner/threads]
+    pub tuning_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Estimate the expected time to find a block offline, without connecting to vecnod
+    Calc(CalcOpt),
+    /// Measure the hashrate with 1 up to all logical CPUs on a synthetic template, without connecting to vecnod
+    Bench(BenchOpt),
+}
+
+#[derive(Debug, Args)]
+pub struct BenchOpt {
+    #[clap(long, default_value = "10")]
+    /// Seconds to mine with every thread count
+    pub duration: u64,
+    #[clap(long = "max-threads")]
+    /// Highest thread count to try [default: number of logical cpus]
+    pub max_threads: Option<u16>,
+    #[clap(long)]
+    /// Save the recommended thread count, so it's used when --threads isn't passed
+    pub save: bool,
+}
+
+#[derive(Debug, Args)]
+#[clap(group(ArgGroup::new("target").required(true)))]
+pub struct CalcOpt {
+    #[clap(long, group = "target")]
+    /// Network difficulty as reported by vecnod
+    pub difficulty: Option<f64>,
+    #[clap(long, group = "target", value_parser = parse_bits)]
+    /// Compact target (`bits`) of a block template, in hex
+    pub bits: Option<u32>,
+    #[clap(long, value_parser = parse_hashrate)]
+    /// Hashrate of the miner in hash/s, accepts K/M/G/T suffixes (e.g. 1.5M)
+    pub hashrate: f64,
+}
+
+fn parse_bits(s: &str) -> Result<u32, &'static str> {
+    let s = s.trim_start_matches("0x");
+    u32::from_str_radix(s, 16).map_err(|_| "bits should be a 32 bit hex number, e.g. 1e7fffff")
+}
+
+fn parse_hashrate(s: &str) -> Result<f64, &'static str> {
+    let err = "hashrate should be a positive number with an optional K/M/G/T suffix, e.g. 1.5M";
+    let s = s.trim().trim_end_matches("hash/s").trim_end_matches("H/s");
+    let (number, multiplier) = match s.char_indices().last().ok_or(err)? {
+        (i, 'k' | 'K') => (&s[..i], 1e3),
+        (i, 'm' | 'M') => (&s[..i], 1e6),
+        (i, 'g' | 'G') => (&s[..i], 1e9),
+        (i, 't' | 'T') => (&s[..i], 1e12),
+        _ => (s, 1.0),
+    };
+    let rate = number.trim().parse::<f64>().map_err(|_| err)? * multiplier;
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(err);
+    }
+    Ok(rate)
+}
+
+fn parse_cpu_percent(s: &str) -> Result<f64, &'static str> {
+    let err = "max-cpu-percent should be a number above 0 and up to 100";
+    match s.trim_end_matches('%').parse::<f64>() {
+        Ok(percent) if percent > 0.0 && percent <= 100.0 => Ok(percent),
+        _ => Err(err),
+    }
+}
+
+fn parse_idle_threshold(s: &str) -> Result<f64, &'static str> {
+    let err = "idle-threshold should be a non negative number of CPUs, e.g. 0.5";
+    match s.parse::<f64>() {
+        Ok(threshold) if threshold.is_finite() && threshold >= 0.0 => Ok(threshold),
+        _ => Err(err),
+    }
+}
+
+fn parse_temperature(s: &str) -> Result<f64, &'static str> {
+    let err = "temperature should be a positive number of degrees Celsius, e.g. 80";
+    match s.trim_end_matches("C").trim_end_matches('°').parse::<f64>() {
+        Ok(celsius) if celsius.is_finite() && celsius > 0.0 => Ok(celsius),
+        _ => Err(err),
+    }
+}
+
+fn parse_hysteresis(s: &str) -> Result<f64, &'static str> {
+    let err = "temp-hysteresis should be a number of degrees Celsius of at least 0, e.g. 5";
+    match s.trim_end_matches("C").trim_end_matches('°').parse::<f64>() {
+        Ok(celsius) if celsius.is_finite() && celsius >= 0.0 => Ok(celsius),
+        _ => Err(err),
+    }
 }
 
 fn parse_devfund_percent(s: &str) -> Result<u16, &'static str> {
@@ -1,32 +1,142 @@
 use crate::{
+    control::Control,
     miner::MinerManager,
     proto::{
         rpc_client::RpcClient, vecnod_message::Payload, GetBlockTemplateRequestMessage, GetInfoRequestMessage,
-        VecnodMessage,
+        RpcBlock, VecnodMessage,
     },
     Error, ShutdownHandler,
 };
+use chrono::Local;
 use log::{error, info, warn};
-use tokio::sync::mpsc::{self, error::SendError, Sender};
-use tokio_stream::wrappers::ReceiverStream;
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::{self, error::SendError, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use tokio_stream::Stream;
 use tonic::{transport::Channel as TonicChannel, Streaming};
 
 static EXTRA_DATA: &str = concat!(env!("CARGO_PKG_VERSION"));
+// How often dead mining threads are looked for
+const SUPERVISE_RATE: Duration = Duration::from_secs(1);
+
+/// A block found by a mining thread, waiting to be sent to vecnod
+#[derive(Debug)]
+pub struct Submission {
+    pub block: RpcBlock,
+    pub found: Instant,
+    /// Id of the template the block was mined on
+    pub template_id: usize,
+    /// How long before the block was found its template was replaced, `None` if it was still current
+    pub stale_for: Option<Duration>,
+}
+
+impl Submission {
+    pub fn new(block: RpcBlock, template_id: usize, stale_for: Option<Duration>) -> Self {
+        Self { block, found: Instant::now(), template_id, stale_for }
+    }
+}
+
+/// Where found blocks go instead of vecnod, to try a build against a real node without submitting anything
+#[derive(Debug, Clone, Default)]
+pub struct DryRun {
+    /// Also appends every found block to this file
+    pub file: Option<PathBuf>,
+}
+
+impl DryRun {
+    /// Appends on the blocking pool, so a slow disk doesn't stall the runtime
+    async fn write_block(path: &Path, submission: &Submission) -> Result<(), Error> {
+        let line = format!(
+            "{} {:x} template #{} {:?}",
+            Local::now().to_rfc3339(),
+            submission.block.block_hash().unwrap_or_default(),
+            submission.template_id,
+            submission.block
+        );
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", line)?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn record_blocks(self, mut submissions: UnboundedReceiver<Submission>) {
+        while let Some(submission) = submissions.recv().await {
+            info!(
+                "Dry run, not submitting block {:x} of template #{}",
+                submission.block.block_hash().unwrap_or_default(),
+                submission.template_id
+            );
+            if let Some(path) = &self.file {
+                if let Err(e) = Self::write_block(path, &submission).await {
+                    warn!("Failed writing the found block to {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+/// The messages to vecnod, where block submissions always jump ahead of the other messages
+struct Outbound {
+    // `None` in a dry run
+    submissions: Option<UnboundedReceiver<Submission>>,
+    messages: Receiver<VecnodMessage>,
+}
+
+impl Stream for Outbound {
+    type Item = VecnodMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(Poll::Ready(Some(submission))) =
+            self.submissions.as_mut().map(|submissions| submissions.poll_recv(cx))
+        {
+            let stale = match submission.stale_for {
+                Some(stale_for) => format!(", its template was replaced {:.2?} earlier", stale_for),
+                None => String::new(),
+            };
+            info!(
+                "Submitting block {:x} of template #{}, {:.2?} after it was found{}",
+                submission.block.block_hash().unwrap_or_default(),
+                submission.template_id,
+                submission.found.elapsed(),
+                stale
+            );
+            return Poll::Ready(Some(VecnodMessage::submit_block(submission.block)));
+        }
+        self.messages.poll_recv(cx)
+    }
+}
 
 #[allow(dead_code)]
 pub struct VecnodHandler {
     client: RpcClient<TonicChannel>,
     pub send_channel: Sender<VecnodMessage>,
+    /// Where mining threads hand off found blocks without blocking
+    pub submit_channel: UnboundedSender<Submission>,
     stream: Streaming<VecnodMessage>,
-    miner_address: String,
+    // A pay address for every thread group
+    pay_addresses: Vec<String>,
+    // Groups of the templates requested but not received yet, vecnod answers them in order
+    pending_templates: VecDeque<usize>,
     mine_when_not_synced: bool,
-    devfund_address: Option<String>,
-    devfund_percent: u16,
-    block_template_ctr: u64,
 }
 
 impl VecnodHandler {
-    pub async fn connect<D>(address: D, miner_address: String, mine_when_not_synced: bool) -> Result<Self, Error>
+    pub async fn connect<D>(
+        address: D,
+        pay_addresses: Vec<String>,
+        mine_when_not_synced: bool,
+        dry_run: Option<DryRun>,
+    ) -> Result<Self, Error>
     where
         D: TryInto<tonic::transport::Endpoint>,
         D::Error: Into<Error>,
@@ -34,47 +144,62 @@ impl VecnodHandler {
         let mut client = RpcClient::connect(address).await?;
         let (send_channel, recv) = mpsc::channel(3);
         send_channel.send(GetInfoRequestMessage {}.into()).await?;
-        send_channel
-            .send(
-                GetBlockTemplateRequestMessage { pay_address: miner_address.clone(), extra_data: EXTRA_DATA.into() }
-                    .into(),
-            )
-            .await?;
-        let stream = client.message_stream(ReceiverStream::new(recv)).await?.into_inner();
+        let (submit_channel, submissions) = mpsc::unbounded_channel();
+        let submissions = match dry_run {
+            Some(dry_run) => {
+                tokio::spawn(dry_run.record_blocks(submissions));
+                None
+            }
+            None => Some(submissions),
+        };
+        let outbound = Outbound { submissions, messages: recv };
+        let stream = client.message_stream(outbound).await?.into_inner();
         Ok(Self {
             client,
             stream,
             send_channel,
-            miner_address,
+            submit_channel,
+            pay_addresses,
+            pending_templates: VecDeque::new(),
             mine_when_not_synced,
-            devfund_address: None,
-            devfund_percent: 0,
-            block_template_ctr: 0,
         })
     }
 
-    pub fn add_devfund(&mut self, address: String, percent: u16) {
-        self.devfund_address = Some(address);
-        self.devfund_percent = percent;
-    }
-
     pub async fn client_send(&self, msg: impl Into<VecnodMessage>) -> Result<(), SendError<VecnodMessage>> {
         self.send_channel.send(msg.into()).await
     }
 
+    /// Requests a template for every thread group
     pub async fn client_get_block_template(&mut self) -> Result<(), SendError<VecnodMessage>> {
-        let pay_address = match &self.devfund_address {
-            Some(devfund_address) if (self.block_template_ctr % 10_000) as u16 <= self.devfund_percent => {
-                devfund_address.clone()
-            }
-            _ => self.miner_address.clone(),
-        };
-        self.block_template_ctr += 1;
-        self.client_send(GetBlockTemplateRequestMessage { pay_address, extra_data: EXTRA_DATA.into() }).await
+        for (group, pay_address) in self.pay_addresses.iter().enumerate() {
+            let request =
+                GetBlockTemplateRequestMessage { pay_address: pay_address.clone(), extra_data: EXTRA_DATA.into() };
+            self.send_channel.send(request.into()).await?;
+            self.pending_templates.push_back(group);
+        }
+        Ok(())
     }
 
-    pub async fn listen(&mut self, miner: &mut MinerManager, shutdown: ShutdownHandler) -> Result<(), Error> {
-        while let Some(msg) = self.stream.message().await? {
+    pub async fn listen(
+        &mut self,
+        miner: &mut MinerManager,
+        control: &mut UnboundedReceiver<Control>,
+        shutdown: ShutdownHandler,
+    ) -> Result<(), Error> {
+        let mut supervise = tokio::time::interval(SUPERVISE_RATE);
+        loop {
+            let msg = tokio::select! {
+                msg = self.stream.message() => msg?,
+                Some(control) = control.recv() => {
+                    miner.handle_control(control);
+                    continue;
+                }
+                _ = supervise.tick() => {
+                    miner.supervise();
+                    continue;
+                }
+            };
+            let Some(msg) = msg else { break };
             if shutdown.is_shutdown() {
                 break;
             }
@@ -89,13 +214,19 @@ impl VecnodHandler {
     async fn handle_message(&mut self, msg: Payload, miner: &mut MinerManager) -> Result<(), Error> {
         match msg {
             Payload::NewBlockTemplateNotification(_) => self.client_get_block_template().await?,
-            Payload::GetBlockTemplateResponse(template) => match (template.block, template.is_synced, template.error) {
-                (Some(b), true, None) => miner.process_block(Some(b))?,
-                (Some(b), false, None) if self.mine_when_not_synced => miner.process_block(Some(b))?,
-                (_, false, None) => miner.process_block(None)?,
-                (_, _, Some(e)) => warn!("GetTemplate returned with an error: {:?}", e),
-                (None, true, None) => error!("No block and No Error!"),
-            },
+            Payload::GetBlockTemplateResponse(template) => {
+                let Some(group) = self.pending_templates.pop_front() else {
+                    warn!("Ignoring a block template that wasn't requested");
+                    return Ok(());
+                };
+                match (template.block, template.is_synced, template.error) {
+                    (Some(b), true, None) => miner.process_block(group, Some(b))?,
+                    (Some(b), false, None) if self.mine_when_not_synced => miner.process_block(group, Some(b))?,
+                    (_, false, None) => miner.process_block(group, None)?,
+                    (_, _, Some(e)) => warn!("GetTemplate returned with an error: {:?}", e),
+                    (None, true, None) => error!("No block and No Error!"),
+                }
+            }
             Payload::SubmitBlockResponse(res) => match res.error {
                 None => info!("Block submitted successfully!"),
                 Some(e) => warn!("Failed submitting block: {:?}", e),
@@ -116,3 +247,50 @@ impl VecnodHandler {
         Ok(())
     }
 }
+
+#[cfg(all(test, not(feature = "shuttle")))]
+mod tests {
+    use super::*;
+    use crate::bench::synthetic_block;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_submissions_go_first() {
+        let (send_channel, messages) = mpsc::channel(3);
+        let (submit_channel, submissions) = mpsc::unbounded_channel();
+        let mut outbound = Outbound { submissions: Some(submissions), messages };
+        send_channel.send(GetInfoRequestMessage {}.into()).await.unwrap();
+        send_channel.send(GetInfoRequestMessage {}.into()).await.unwrap();
+        submit_channel.send(Submission::new(synthetic_block(0x1d00ffff), 0, None)).unwrap();
+
+        let is_submission =
+            |msg: Option<VecnodMessage>| matches!(msg.unwrap().payload, Some(Payload::SubmitBlockRequest(_)));
+        assert!(is_submission(outbound.next().await));
+        assert!(!is_submission(outbound.next().await));
+        submit_channel.send(Submission::new(synthetic_block(0x1d00ffff), 0, None)).unwrap();
+        assert!(is_submission(outbound.next().await));
+        assert!(!is_submission(outbound.next().await));
+
+        drop(send_channel);
+        assert!(outbound.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_records_blocks() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("blocks.log");
+        let (submit_channel, submissions) = mpsc::unbounded_channel();
+        let block = synthetic_block(0x1d00ffff);
+        submit_channel.send(Submission::new(block.clone(), 7, None)).unwrap();
+        submit_channel.send(Submission::new(block.clone(), 8, None)).unwrap();
+        drop(submit_channel);
+        DryRun { file: Some(path.clone()) }.record_blocks(submissions).await;
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let hash = format!("{:x}", block.block_hash().unwrap());
+        assert!(lines[0].contains(&format!("{} template #7 RpcBlock", hash)));
+        assert!(lines[1].contains("template #8"));
+    }
+}
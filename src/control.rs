@@ -0,0 +1,37 @@
+use crate::Error;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+/// Commands changing the miner while it's running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    AddThread,
+    RemoveThread,
+}
+
+/// Translates `SIGUSR1` into [`Control::AddThread`] and `SIGUSR2` into [`Control::RemoveThread`]
+#[cfg(unix)]
+pub fn listen_signals() -> Result<UnboundedReceiver<Control>, Error> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut add_thread = signal(SignalKind::user_defined1())?;
+    let mut remove_thread = signal(SignalKind::user_defined2())?;
+    let (send, recv) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let control = tokio::select! {
+                Some(()) = add_thread.recv() => Control::AddThread,
+                Some(()) = remove_thread.recv() => Control::RemoveThread,
+                else => return,
+            };
+            if send.send(control).is_err() {
+                return;
+            }
+        }
+    });
+    Ok(recv)
+}
+
+#[cfg(not(unix))]
+pub fn listen_signals() -> Result<UnboundedReceiver<Control>, Error> {
+    Ok(mpsc::unbounded_channel().1)
+}
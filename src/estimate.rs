@@ -0,0 +1,168 @@
+use crate::{
+    cli::CalcOpt,
+    miner::MinerManager,
+    target::{self, Uint256},
+};
+use std::{fmt, time::Duration};
+
+const SECONDS_IN_DAY: f64 = 24.0 * 60.0 * 60.0;
+/// 2^255, the target that vecnod reports as difficulty 1.
+const DIFFICULTY_ONE_TARGET: f64 = 5.78960446186580977117854925043439539266e76;
+
+/// Expected number of hashes it takes to find a block for a given target.
+/// Every hash has a `(target + 1) / 2^256` chance of being a solution.
+#[inline]
+pub fn expected_hashes(target: Uint256) -> f64 {
+    2f64.powi(256) / (target.as_f64() + 1.0)
+}
+
+#[inline]
+pub fn expected_hashes_from_bits(bits: u32) -> f64 {
+    expected_hashes(target::u256_from_compact_target(bits))
+}
+
+/// Expected hashes for a difficulty as reported by vecnod (`2^255 / target`).
+#[inline]
+pub fn expected_hashes_from_difficulty(difficulty: f64) -> f64 {
+    2f64.powi(256) / (DIFFICULTY_ONE_TARGET / difficulty + 1.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    /// Mean time between blocks found at the given hashrate
+    pub expected_time: Duration,
+    /// Probability of finding at least one block in the next 24 hours, in the range [0, 1]
+    pub daily_probability: f64,
+}
+
+impl Estimate {
+    pub fn new(expected_hashes: f64, hashrate: f64) -> Option<Self> {
+        if !(hashrate > 0.0 && expected_hashes.is_finite() && expected_hashes > 0.0) {
+            return None;
+        }
+        let expected_secs = expected_hashes / hashrate;
+        // Block finding is a Poisson process, so the chance of at least one success is 1 - e^(-expected successes)
+        let daily_probability = -(-SECONDS_IN_DAY / expected_secs).exp_m1();
+        let expected_time = Duration::try_from_secs_f64(expected_secs).unwrap_or(Duration::MAX);
+        Some(Self { expected_time, daily_probability })
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected time to block: {}, chance of a block in the next 24h: {:.2}%",
+            HumanDuration(self.expected_time),
+            self.daily_probability * 100.0
+        )
+    }
+}
+
+/// Tracks the "effort" spent since the last found block: the hashes done divided by the hashes expected.
+/// 100% means the miner is exactly as lucky as expected, and since the target can change between templates
+/// every batch of hashes is weighted by the target it was mined against.
+#[derive(Debug, Default)]
+pub struct Effort {
+    current: f64,
+}
+
+impl Effort {
+    pub fn add_hashes(&mut self, hashes: u64, expected_hashes: f64) {
+        if expected_hashes.is_finite() && expected_hashes > 0.0 {
+            self.current += hashes as f64 / expected_hashes;
+        }
+    }
+
+    /// Resets the effort after a block was found and returns the effort it took to find it.
+    pub fn block_found(&mut self) -> f64 {
+        std::mem::take(&mut self.current)
+    }
+
+    pub fn current(&self) -> f64 {
+        self.current
+    }
+}
+
+/// Runs the `calc` subcommand
+pub fn calc(opt: &CalcOpt) {
+    let expected_hashes = match (opt.bits, opt.difficulty) {
+        (Some(bits), _) => expected_hashes_from_bits(bits),
+        (None, Some(difficulty)) => expected_hashes_from_difficulty(difficulty),
+        (None, None) => unreachable!("clap requires either --bits or --difficulty"),
+    };
+    let (rate, suffix) = MinerManager::hash_suffix(opt.hashrate);
+    println!("Hashrate: {:.2} {}", rate, suffix);
+    println!("Expected hashes per block: {:.4e}", expected_hashes);
+    match Estimate::new(expected_hashes, opt.hashrate) {
+        Some(estimate) => {
+            println!("Expected time to block: {}", HumanDuration(estimate.expected_time));
+            println!("Chance of a block in the next 24h: {:.4}%", estimate.daily_probability * 100.0);
+        }
+        None => println!("Target is invalid, no block can be found"),
+    }
+}
+
+pub struct HumanDuration(pub Duration);
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+        let (days, hours, minutes, seconds) = (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60, secs % 60);
+        match (days, hours, minutes) {
+            (d, h, _) if d >= 365 => write!(f, "{:.1} years", (d as f64 + h as f64 / 24.0) / 365.0),
+            (d, h, m) if d > 0 => write!(f, "{}d {}h {}m", d, h, m),
+            (_, h, m) if h > 0 => write!(f, "{}h {}m {}s", h, m, seconds),
+            (_, _, m) if m > 0 => write!(f, "{}m {}s", m, seconds),
+            _ => write!(f, "{:.2}s", self.0.as_secs_f64()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expected_hashes() {
+        // A target of 2^255 - 1 is hit by half of all hashes
+        let half = Uint256([u64::MAX, u64::MAX, u64::MAX, u64::MAX >> 1]);
+        assert!((expected_hashes(half) - 2.0).abs() < 1e-9);
+        assert!((expected_hashes(Uint256([u64::MAX; 4])) - 1.0).abs() < 1e-9);
+        // 0x1e7fffff => 0x7fffff << 216
+        let expected = 2f64.powi(256) / (0x7fffff as f64 * 2f64.powi(216));
+        assert!((expected_hashes_from_bits(0x1e7fffff) / expected - 1.0).abs() < 1e-6);
+        assert!((expected_hashes_from_difficulty(1.0) - 2.0).abs() < 1e-9);
+        assert!((expected_hashes_from_difficulty(1e12) / 2e12 - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_estimate() {
+        assert_eq!(Estimate::new(1e6, 0.0), None);
+        let estimate = Estimate::new(SECONDS_IN_DAY * 1000.0, 1000.0).unwrap();
+        assert_eq!(estimate.expected_time, Duration::from_secs(86_400));
+        assert!((estimate.daily_probability - (1.0 - (-1f64).exp())).abs() < 1e-12);
+        let estimate = Estimate::new(1e30, 1.0).unwrap();
+        assert!(estimate.daily_probability > 0.0 && estimate.daily_probability < 1e-20);
+    }
+
+    #[test]
+    fn test_effort() {
+        let mut effort = Effort::default();
+        effort.add_hashes(500, 1000.0);
+        effort.add_hashes(500, 250.0);
+        assert!((effort.current() - 2.5).abs() < 1e-12);
+        effort.add_hashes(500, f64::INFINITY);
+        assert!((effort.block_found() - 2.5).abs() < 1e-12);
+        assert_eq!(effort.current(), 0.0);
+    }
+
+    #[test]
+    fn test_human_duration() {
+        assert_eq!(HumanDuration(Duration::from_millis(1500)).to_string(), "1.50s");
+        assert_eq!(HumanDuration(Duration::from_secs(61)).to_string(), "1m 1s");
+        assert_eq!(HumanDuration(Duration::from_secs(3_723)).to_string(), "1h 2m 3s");
+        assert_eq!(HumanDuration(Duration::from_secs(90_061)).to_string(), "1d 1h 1m");
+        assert_eq!(HumanDuration(Duration::from_secs(2 * 365 * 86_400)).to_string(), "2.0 years");
+    }
+}
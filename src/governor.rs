@@ -0,0 +1,236 @@
+use log::info;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// A condition of the machine that can limit how many mining threads are active
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LimitSource {
+    Load,
+    Thermal,
+    Schedule,
+    Battery,
+    Processes,
+}
+
+impl fmt::Display for LimitSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitSource::Load => write!(f, "system load"),
+            LimitSource::Thermal => write!(f, "temperature"),
+            LimitSource::Schedule => write!(f, "the mining schedule"),
+            LimitSource::Battery => write!(f, "battery power"),
+            LimitSource::Processes => write!(f, "running processes"),
+        }
+    }
+}
+
+/// What a source does to mining while its condition holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitAction {
+    Pause,
+    /// Keep mining with at most this many threads
+    Threads(usize),
+}
+
+impl LimitAction {
+    /// The thread limit to set, 0 pauses mining
+    pub fn limit(self) -> usize {
+        match self {
+            LimitAction::Pause => 0,
+            LimitAction::Threads(threads) => threads,
+        }
+    }
+}
+
+impl FromStr for LimitAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pause" => Ok(Self::Pause),
+            threads => match threads.parse() {
+                Ok(0) => Ok(Self::Pause),
+                Ok(threads) => Ok(Self::Threads(threads)),
+                Err(_) => Err(format!("should be `pause` or a number of threads, got: {}", s)),
+            },
+        }
+    }
+}
+
+/// Combines the limits of all the sources, the strictest one wins.
+/// Threads above the limit are parked without leaving the pool, so they resume instantly.
+#[derive(Debug)]
+pub struct Governor {
+    limits: Mutex<BTreeMap<LimitSource, usize>>,
+    active_limit: AtomicUsize,
+    pool_size: AtomicUsize,
+    paused: Mutex<PauseClock>,
+}
+
+/// Total time mining was paused, so it can be left out of the hashrate
+#[derive(Debug, Default)]
+struct PauseClock {
+    since: Option<Instant>,
+    total: Duration,
+}
+
+impl Default for Governor {
+    fn default() -> Self {
+        Self {
+            limits: Mutex::new(BTreeMap::new()),
+            active_limit: AtomicUsize::new(usize::MAX),
+            pool_size: AtomicUsize::new(0),
+            paused: Mutex::default(),
+        }
+    }
+}
+
+impl Governor {
+    /// Sets the maximum number of active threads for a source, 0 pauses mining and `None` removes the limit
+    pub fn set_limit(&self, source: LimitSource, limit: Option<usize>) {
+        let mut limits = self.limits.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = limits.get(&source).copied();
+        if previous == limit {
+            return;
+        }
+        match limit {
+            Some(0) => info!("Pausing mining because of {}", source),
+            Some(limit) => info!("Limiting mining to {} threads because of {}", limit, source),
+            None => info!("Mining is no longer limited by {}", source),
+        }
+        match limit {
+            Some(limit) => limits.insert(source, limit),
+            None => limits.remove(&source),
+        };
+        let active_limit = limits.values().copied().min().unwrap_or(usize::MAX);
+        self.active_limit.store(active_limit, Ordering::Relaxed);
+
+        let mut paused = self.paused.lock().unwrap_or_else(|e| e.into_inner());
+        match (active_limit == 0, paused.since) {
+            (true, None) => paused.since = Some(Instant::now()),
+            (false, Some(since)) => {
+                paused.total += since.elapsed();
+                paused.since = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Maximum number of active threads, `usize::MAX` when unlimited
+    #[inline(always)]
+    pub fn active_limit(&self) -> usize {
+        self.active_limit.load(Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    pub fn is_active(&self, slot: usize) -> bool {
+        slot < self.active_limit()
+    }
+
+    pub fn set_pool_size(&self, pool_size: usize) {
+        self.pool_size.store(pool_size, Ordering::Relaxed);
+    }
+
+    pub fn pool_size(&self) -> usize {
+        self.pool_size.load(Ordering::Relaxed)
+    }
+
+    /// Number of threads in the pool that aren't parked
+    pub fn active_threads(&self) -> usize {
+        self.pool_size().min(self.active_limit())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.active_limit() == 0
+    }
+
+    /// Total time mining was paused since the start, including the current pause
+    pub fn paused_time(&self) -> Duration {
+        let paused = self.paused.lock().unwrap_or_else(|e| e.into_inner());
+        paused.total + paused.since.map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    /// The sources limiting mining right now, with their limits
+    pub fn limits(&self) -> Vec<(LimitSource, usize)> {
+        let limits = self.limits.lock().unwrap_or_else(|e| e.into_inner());
+        limits.iter().map(|(&source, &limit)| (source, limit)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_governor() {
+        let governor = Governor::default();
+        assert!(governor.is_active(1000));
+        assert!(!governor.is_paused());
+
+        governor.set_pool_size(4);
+        assert_eq!(governor.active_threads(), 4);
+        governor.set_limit(LimitSource::Load, Some(2));
+        assert_eq!(governor.active_threads(), 2);
+        assert!(governor.is_active(1));
+        assert!(!governor.is_active(2));
+        assert_eq!(governor.limits(), vec![(LimitSource::Load, 2)]);
+
+        governor.set_limit(LimitSource::Load, Some(0));
+        assert!(governor.is_paused());
+        assert!(!governor.is_active(0));
+
+        governor.set_limit(LimitSource::Load, None);
+        assert_eq!(governor.active_limit(), usize::MAX);
+        assert!(governor.limits().is_empty());
+    }
+
+    #[test]
+    fn test_strictest_limit_wins() {
+        let governor = Governor::default();
+        governor.set_pool_size(8);
+        governor.set_limit(LimitSource::Load, Some(6));
+        governor.set_limit(LimitSource::Thermal, Some(3));
+        assert_eq!(governor.active_threads(), 3);
+        governor.set_limit(LimitSource::Thermal, Some(7));
+        assert_eq!(governor.active_threads(), 6);
+        governor.set_limit(LimitSource::Load, None);
+        assert_eq!(governor.active_threads(), 7);
+        assert_eq!(governor.limits(), vec![(LimitSource::Thermal, 7)]);
+    }
+
+    #[test]
+    fn test_limit_action() {
+        assert_eq!("pause".parse(), Ok(LimitAction::Pause));
+        assert_eq!("0".parse(), Ok(LimitAction::Pause));
+        assert_eq!("2".parse::<LimitAction>().map(LimitAction::limit), Ok(2));
+        assert!("later".parse::<LimitAction>().is_err());
+    }
+
+    #[test]
+    fn test_paused_time() {
+        let governor = Governor::default();
+        governor.set_limit(LimitSource::Thermal, Some(1));
+        assert_eq!(governor.paused_time(), Duration::ZERO);
+
+        governor.set_limit(LimitSource::Schedule, Some(0));
+        std::thread::sleep(Duration::from_millis(20));
+        let during = governor.paused_time();
+        assert!(during >= Duration::from_millis(20));
+        // Still paused when only one of the sources lets go
+        governor.set_limit(LimitSource::Load, Some(0));
+        governor.set_limit(LimitSource::Schedule, None);
+        governor.set_limit(LimitSource::Load, None);
+        let after = governor.paused_time();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(governor.paused_time(), after);
+        assert!(after >= during);
+    }
+}
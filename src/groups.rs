@@ -0,0 +1,143 @@
+use crate::Error;
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+// How often the threads are dealt out to the groups again
+pub const ROTATE_RATE: Duration = Duration::from_secs(10);
+// 1/φ, its multiples spread any run of consecutive indices evenly over [0, 1)
+const GOLDEN_RATIO_FRACTION: f64 = 0.618_033_988_749_894_9;
+
+/// A pay address and the share of the hashes mined for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadGroup {
+    pub address: String,
+    pub share: f64,
+}
+
+impl FromStr for ThreadGroup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("group should be ADDRESS=PERCENT, e.g. vecno:qq...=5, got: {}", s);
+        let (address, percent) = s.rsplit_once('=').ok_or_else(err)?;
+        let percent = percent.trim_end_matches('%').parse::<f64>().map_err(|_| err())?;
+        if address.is_empty() || !(percent > 0.0 && percent < 100.0) {
+            return Err(err());
+        }
+        Ok(Self { address: address.to_owned(), share: percent / 100.0 })
+    }
+}
+
+/// Puts the mining address first, with the share the other groups leave
+pub fn with_mining_address(mining_address: String, others: Vec<ThreadGroup>) -> Result<Vec<ThreadGroup>, Error> {
+    let share = 1.0 - others.iter().map(|group| group.share).sum::<f64>();
+    if share <= 0.0 {
+        return Err("The groups leave no share for the mining address".into());
+    }
+    Ok(std::iter::once(ThreadGroup { address: mining_address, share }).chain(others).collect())
+}
+
+/// The group at `index` of a low-discrepancy sequence, so every run of indices is split close to the shares
+fn pick(shares: &[f64], index: u64) -> usize {
+    let point = (index as f64 * GOLDEN_RATIO_FRACTION).fract();
+    let mut cumulative = 0.0;
+    for (group, share) in shares.iter().enumerate() {
+        cumulative += share;
+        if point < cumulative {
+            return group;
+        }
+    }
+    shares.len() - 1
+}
+
+/// Deals the mining threads out to the groups. The deal changes every [`ROTATE_RATE`],
+/// so the hashes are split by the shares even when a group is worth a fraction of a thread.
+#[derive(Debug)]
+pub struct ThreadGroups {
+    addresses: Vec<String>,
+    shares: Vec<f64>,
+    started: Instant,
+    hashes: Vec<AtomicU64>,
+}
+
+impl ThreadGroups {
+    /// Without any group, all threads mine a single group
+    pub fn new(groups: &[ThreadGroup]) -> Self {
+        let (addresses, shares) = match groups {
+            [] => (vec![String::new()], vec![1.0]),
+            groups => groups.iter().map(|group| (group.address.clone(), group.share)).unzip(),
+        };
+        let hashes = shares.iter().map(|_| AtomicU64::new(0)).collect();
+        Self { addresses, shares, started: Instant::now(), hashes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shares.len()
+    }
+
+    pub fn address(&self, group: usize) -> &str {
+        &self.addresses[group]
+    }
+
+    /// The group the thread in `slot` mines for right now, out of `active_threads`
+    pub fn group_of(&self, slot: usize, active_threads: usize) -> usize {
+        if self.shares.len() == 1 {
+            return 0;
+        }
+        let rotation = self.started.elapsed().as_secs() / ROTATE_RATE.as_secs();
+        pick(&self.shares, rotation * active_threads as u64 + slot as u64)
+    }
+
+    pub fn add_hashes(&self, group: usize, hashes: u64) {
+        self.hashes[group].fetch_add(hashes, Ordering::Relaxed);
+    }
+
+    /// The fraction of the hashes every group got so far
+    pub fn hash_split(&self) -> Vec<f64> {
+        let hashes: Vec<u64> = self.hashes.iter().map(|hashes| hashes.load(Ordering::Relaxed)).collect();
+        let total = hashes.iter().sum::<u64>().max(1) as f64;
+        hashes.into_iter().map(|hashes| hashes as f64 / total).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_groups() {
+        let group: ThreadGroup = "vecno:qqtsqwxa=5".parse().unwrap();
+        assert_eq!(group, ThreadGroup { address: "vecno:qqtsqwxa".to_owned(), share: 0.05 });
+        assert!("vecno:qqtsqwxa".parse::<ThreadGroup>().is_err());
+        assert!("vecno:qqtsqwxa=100".parse::<ThreadGroup>().is_err());
+        assert!("=5".parse::<ThreadGroup>().is_err());
+
+        let groups = with_mining_address("miner".to_owned(), vec![group.clone(), group.clone()]).unwrap();
+        assert_eq!(
+            groups.iter().map(|group| group.address.as_str()).collect::<Vec<_>>(),
+            ["miner", group.address.as_str(), group.address.as_str()]
+        );
+        assert!((groups[0].share - 0.9).abs() < 1e-9);
+        let half = ThreadGroup { share: 0.5, ..group };
+        assert!(with_mining_address("miner".to_owned(), vec![half.clone(), half]).is_err());
+    }
+
+    #[test]
+    fn test_pick_follows_shares() {
+        let shares = [0.95, 0.05];
+        // Even with 4 threads the small group gets its share of the thread time over the rotations
+        let picks: Vec<usize> = (0..4000).map(|index| pick(&shares, index)).collect();
+        let small = picks.iter().filter(|&&group| group == 1).count();
+        assert!((190..=210).contains(&small), "{}", small);
+        // And the groups are interleaved rather than taking turns in long runs
+        assert!(picks.chunks(100).all(|run| run.contains(&1)));
+
+        let groups = ThreadGroups::new(&[]);
+        assert_eq!((groups.len(), groups.group_of(3, 4)), (1, 0));
+        groups.add_hashes(0, 128);
+        assert_eq!(groups.hash_split(), vec![1.0]);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{
+    governor::{Governor, LimitSource},
+    Error,
+};
+use log::{debug, warn};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+pub const PROC_ROOT: &str = "/proc";
+const POLL_RATE: Duration = Duration::from_secs(5);
+
+/// What to do when other workloads need the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleAction {
+    /// Stop mining completely
+    Pause,
+    /// Only leave the CPUs the other workloads need
+    Shrink,
+}
+
+impl FromStr for IdleAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pause" => Ok(Self::Pause),
+            "shrink" => Ok(Self::Shrink),
+            _ => Err(format!("idle-action should be either `pause` or `shrink`, got: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IdleConfig {
+    pub action: IdleAction,
+    /// CPUs of non-miner usage that are ignored, so background noise doesn't stop mining
+    pub threshold: f64,
+    /// How long the other workloads need to be quiet before mining resumes
+    pub quiet_period: Duration,
+    pub proc_root: PathBuf,
+}
+
+/// Cumulative CPU counters, in clock ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuTimes {
+    pub total: u64,
+    pub busy: u64,
+    /// CPU time used by the miner itself
+    pub miner: u64,
+    pub num_cpus: usize,
+}
+
+impl CpuTimes {
+    pub fn read(proc_root: &Path) -> Result<Self, Error> {
+        let stat = fs::read_to_string(proc_root.join("stat"))?;
+        let mut lines = stat.lines();
+        let cpu_line = lines.next().filter(|line| line.starts_with("cpu ")).ok_or("Missing cpu line in stat")?;
+        let fields = cpu_line.split_whitespace().skip(1).map(u64::from_str).collect::<Result<Vec<_>, _>>()?;
+        if fields.len() < 4 {
+            return Err(format!("Invalid cpu line in stat: {}", cpu_line).into());
+        }
+        // user nice system idle iowait irq softirq steal [guest guest_nice], guest time is already part of user time
+        let total: u64 = fields.iter().take(8).sum();
+        let idle = fields[3] + fields.get(4).copied().unwrap_or(0);
+        let num_cpus = lines.filter(|line| line.starts_with("cpu")).count();
+
+        let self_stat = fs::read_to_string(proc_root.join("self/stat"))?;
+        // The process name can contain spaces and parentheses, so skip everything before the last one
+        let after_comm = self_stat.rsplit_once(')').ok_or("Invalid self/stat")?.1;
+        // Fields after the name start at `state`, the 3rd field, while `utime` and `stime` are the 14th and 15th
+        let mut times = after_comm.split_whitespace().skip(11).map(u64::from_str);
+        let (utime, stime) = match (times.next(), times.next()) {
+            (Some(utime), Some(stime)) => (utime?, stime?),
+            _ => return Err("Invalid self/stat".into()),
+        };
+
+        Ok(Self { total, busy: total - idle, miner: utime + stime, num_cpus: num_cpus.max(1) })
+    }
+
+    /// How many CPUs were busy with anything but the miner between two readings
+    pub fn other_usage_since(&self, earlier: &CpuTimes) -> f64 {
+        let total = self.total.saturating_sub(earlier.total);
+        if total == 0 {
+            return 0.0;
+        }
+        let busy = self.busy.saturating_sub(earlier.busy);
+        let miner = self.miner.saturating_sub(earlier.miner);
+        busy.saturating_sub(miner) as f64 / total as f64 * self.num_cpus as f64
+    }
+}
+
+/// Decides on a thread limit from the non-miner CPU demand.
+/// Limits apply immediately, but are only lifted after the demand has been quiet for the quiet period.
+#[derive(Debug)]
+pub struct IdleWatcher {
+    config: IdleConfig,
+    limit: Option<usize>,
+    last_pressure: Option<Instant>,
+}
+
+impl IdleWatcher {
+    pub fn new(config: IdleConfig) -> Self {
+        Self { config, limit: None, last_pressure: None }
+    }
+
+    fn wanted_limit(&self, other_demand: f64, num_cpus: usize) -> Option<usize> {
+        if other_demand <= self.config.threshold {
+            return None;
+        }
+        match self.config.action {
+            IdleAction::Pause => Some(0),
+            IdleAction::Shrink => Some((num_cpus as f64 - other_demand).floor().max(0.0) as usize),
+        }
+    }
+
+    /// Feeds the non-miner CPU demand (in CPUs) and returns the thread limit to apply
+    pub fn update(&mut self, other_demand: f64, num_cpus: usize, now: Instant) -> Option<usize> {
+        let wanted = self.wanted_limit(other_demand, num_cpus);
+        let stricter = match (wanted, self.limit) {
+            (Some(wanted), Some(limit)) => wanted <= limit,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if stricter {
+            self.limit = wanted;
+            self.last_pressure = Some(now);
+        } else if self.last_pressure.is_none_or(|last| now.duration_since(last) >= self.config.quiet_period) {
+            self.limit = wanted;
+            // A looser limit is still a limit, lifting it requires another quiet period
+            if wanted.is_some() {
+                self.last_pressure = Some(now);
+            }
+        }
+        self.limit
+    }
+}
+
+/// Polls the load of the machine and limits mining through the governor
+pub async fn watch(config: IdleConfig, governor: Arc<Governor>) {
+    let proc_root = config.proc_root.clone();
+    let mut watcher = IdleWatcher::new(config);
+    let mut ticker = tokio::time::interval(POLL_RATE);
+    let mut last_times: Option<CpuTimes> = None;
+    loop {
+        ticker.tick().await;
+        let times = match CpuTimes::read(&proc_root) {
+            Ok(times) => times,
+            Err(e) => {
+                warn!("Failed reading CPU usage from {}: {}", proc_root.display(), e);
+                continue;
+            }
+        };
+        let Some(earlier) = last_times.replace(times) else { continue };
+        // The load average isn't used, it still counts the miner's threads for minutes after they're parked
+        let other_demand = times.other_usage_since(&earlier);
+        debug!("Non-miner CPU usage: {:.2} CPUs", other_demand);
+        let limit = watcher.update(other_demand, times.num_cpus, Instant::now());
+        governor.set_limit(LimitSource::Load, limit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_proc(root: &Path, stat_cpu: &str, miner: (u64, u64)) {
+        let stat = format!("cpu  {}\ncpu0 0 0 0 0\ncpu1 0 0 0 0\ncpu2 0 0 0 0\ncpu3 0 0 0 0\nintr 1 2 3\n", stat_cpu);
+        fs::write(root.join("stat"), stat).unwrap();
+        fs::create_dir_all(root.join("self")).unwrap();
+        let self_stat = format!("1234 (vecno miner) S 1 2 3 4 5 6 7 8 9 10 {} {} 0 0 20 0 8 0", miner.0, miner.1);
+        fs::write(root.join("self/stat"), self_stat).unwrap();
+    }
+
+    #[test]
+    fn test_read_cpu_times() {
+        let root = TempDir::new().unwrap();
+        write_proc(root.path(), "100 10 50 800 40 0 0 0 0 0", (70, 10));
+        let times = CpuTimes::read(root.path()).unwrap();
+        assert_eq!(times, CpuTimes { total: 1000, busy: 160, miner: 80, num_cpus: 4 });
+
+        // 400 ticks passed, 200 busy of which the miner used 100, on 4 CPUs
+        write_proc(root.path(), "250 10 100 1000 40 0 0 0 0 0", (160, 20));
+        let later = CpuTimes::read(root.path()).unwrap();
+        assert_eq!(later.other_usage_since(&times), 1.0);
+        assert_eq!(times.other_usage_since(&times), 0.0);
+
+        assert!(CpuTimes::read(TempDir::new().unwrap().path()).is_err());
+    }
+
+    fn config(action: IdleAction) -> IdleConfig {
+        IdleConfig { action, threshold: 0.5, quiet_period: Duration::from_secs(60), proc_root: PROC_ROOT.into() }
+    }
+
+    #[test]
+    fn test_idle_watcher_shrink() {
+        let mut watcher = IdleWatcher::new(config(IdleAction::Shrink));
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+        assert_eq!(watcher.update(0.2, 8, secs(0)), None);
+        assert_eq!(watcher.update(2.5, 8, secs(5)), Some(5));
+        // More demand shrinks immediately
+        assert_eq!(watcher.update(6.0, 8, secs(10)), Some(2));
+        // Less demand only grows back after the quiet period
+        assert_eq!(watcher.update(1.0, 8, secs(15)), Some(2));
+        assert_eq!(watcher.update(1.0, 8, secs(69)), Some(2));
+        assert_eq!(watcher.update(1.0, 8, secs(70)), Some(7));
+        assert_eq!(watcher.update(0.0, 8, secs(75)), Some(7));
+        assert_eq!(watcher.update(0.0, 8, secs(130)), None);
+    }
+
+    #[test]
+    fn test_idle_watcher_pause() {
+        let mut watcher = IdleWatcher::new(config(IdleAction::Pause));
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+        assert_eq!(watcher.update(0.5, 8, secs(0)), None);
+        assert_eq!(watcher.update(0.6, 8, secs(5)), Some(0));
+        assert_eq!(watcher.update(0.0, 8, secs(10)), Some(0));
+        // Pressure again restarts the quiet period
+        assert_eq!(watcher.update(3.0, 8, secs(40)), Some(0));
+        assert_eq!(watcher.update(0.0, 8, secs(90)), Some(0));
+        assert_eq!(watcher.update(0.0, 8, secs(100)), None);
+    }
+
+    #[test]
+    fn test_resume_after_burst() {
+        let root = TempDir::new().unwrap();
+        let mut watcher = IdleWatcher::new(config(IdleAction::Pause));
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+        // The miner was paused by a burst of other work
+        write_proc(root.path(), "100 0 0 300 0 0 0 0 0 0", (0, 0));
+        let earlier = CpuTimes::read(root.path()).unwrap();
+        assert_eq!(watcher.update(3.0, 4, secs(0)), Some(0));
+        // Once the burst is over only the CPU usage since the last reading counts, not how busy the last minute was
+        write_proc(root.path(), "110 0 0 690 0 0 0 0 0 0", (0, 0));
+        let other_demand = CpuTimes::read(root.path()).unwrap().other_usage_since(&earlier);
+        assert!((other_demand - 0.1).abs() < 1e-9);
+        assert_eq!(watcher.update(other_demand, 4, secs(60)), None);
+    }
+
+    #[test]
+    fn test_parse_idle_action() {
+        assert_eq!("pause".parse(), Ok(IdleAction::Pause));
+        assert_eq!("Shrink".parse(), Ok(IdleAction::Shrink));
+        assert!("stop".parse::<IdleAction>().is_err());
+    }
+}
@@ -14,15 +14,42 @@ use std::{
 };
 
 use crate::{
-    cli::Opt, client::VecnodHandler, miner::MinerManager, proto::NotifyNewBlockTemplateRequestMessage, target::Uint256,
+    cgroup::CpuBudget,
+    cli::{Command, Opt},
+    client::{DryRun, VecnodHandler},
+    groups::ThreadGroup,
+    idle::IdleConfig,
+    miner::{MinerConfig, MinerManager},
+    power::BatteryConfig,
+    processes::ProcessConfig,
+    proto::NotifyNewBlockTemplateRequestMessage,
+    target::Uint256,
+    thermal::ThermalConfig,
 };
 
+mod affinity;
+mod bench;
+mod cgroup;
 mod cli;
 mod client;
+mod control;
+mod estimate;
+mod governor;
+mod groups;
+mod idle;
 mod miner;
+mod numa;
 mod pow;
+mod power;
+mod priority;
+mod processes;
+mod schedule;
+mod stale;
+mod stats;
 mod swap_rust;
 mod target;
+mod thermal;
+mod throttle;
 mod vecnod_messages;
 
 pub mod proto {
@@ -60,6 +87,10 @@ impl Drop for ShutdownOnDrop {
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let mut opt: Opt = Opt::parse();
+    if let Some(Command::Calc(calc)) = &opt.command {
+        estimate::calc(calc);
+        return Ok(());
+    }
     opt.process()?;
 
     let mut builder = env_logger::builder();
@@ -72,29 +103,124 @@ async fn main() -> Result<(), Error> {
     }
     builder.init();
 
-    let throttle = opt.throttle.map(Duration::from_millis);
+    let tuning_file = bench::tuning_file(opt.tuning_file.as_deref());
+    if let Some(Command::Bench(bench_opt)) = &opt.command {
+        return bench::run(bench_opt, tuning_file.as_deref()).await;
+    }
+    let mining_address = opt.mining_address.clone().ok_or("Missing mining address")?;
+    let mut other_groups = opt.groups.clone();
+    // A 0% devfund would be a group no thread is ever dealt to
+    if let Some(devfund_address) = opt.devfund_address.as_ref().filter(|_| opt.devfund_percent > 0) {
+        info!(
+            "devfund enabled, mining {}.{}% of the hashes to devfund address: {} ",
+            opt.devfund_percent / 100,
+            opt.devfund_percent % 100,
+            devfund_address
+        );
+        let share = f64::from(opt.devfund_percent) / 10_000.0;
+        other_groups.push(ThreadGroup { address: devfund_address.clone(), share });
+    }
+    let thread_groups = groups::with_mining_address(mining_address, other_groups)?;
+    if thread_groups.len() > 1 {
+        for group in &thread_groups {
+            info!("Thread group: {:.2}% of the hashes to {}", group.share * 100.0, group.address);
+        }
+    }
+    let pay_addresses: Vec<String> = thread_groups.iter().map(|group| group.address.clone()).collect();
+    if opt.num_threads.is_none() {
+        if let Some(path) = &tuning_file {
+            match bench::saved_threads(path) {
+                Ok(Some(threads)) => {
+                    info!("Using {} threads as recommended by the benchmark in {}", threads, path.display());
+                    opt.num_threads = Some(threads);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Failed reading the thread count from {}: {}", path.display(), e),
+            }
+        }
+    }
+    // Pinned threads default to a thread per pinned CPU instead
+    if opt.num_threads.is_none() && opt.cpu_affinity.is_none() {
+        opt.num_threads = Some(CpuBudget::default_threads(&opt.cgroup_root, &opt.proc_root));
+    }
+
     let shutdown = ShutdownHandler(Arc::new(AtomicBool::new(false)));
     let _shutdown_when_dropped = shutdown.arm();
 
-    while !shutdown.is_shutdown() {
-        let mut client =
-            VecnodHandler::connect(opt.vecnod_address.clone(), opt.mining_address.clone(), opt.mine_when_not_synced)
-                .await?;
-        if let Some(devfund_address) = &opt.devfund_address {
-            client.add_devfund(devfund_address.clone(), opt.devfund_percent);
-            info!(
-                "devfund enabled, mining {}.{}% of the time to devfund address: {} ",
-                opt.devfund_percent / 100,
-                opt.devfund_percent % 100,
-                devfund_address
-            );
+    let config = MinerConfig {
+        n_cpus: opt.num_threads,
+        throttle: opt.throttle.map(Duration::from_millis),
+        cpus: opt.cpu_affinity.as_ref().map(|affinity| affinity.cpus(&opt.sys_root)).transpose()?,
+        priority: opt.priority,
+        max_cpu_percent: opt.max_cpu_percent,
+        max_hashrate: opt.max_hashrate,
+        stale_policy: opt.stale_policy,
+        groups: thread_groups,
+        quiet: false,
+    };
+    match &config.cpus {
+        Some(cpus) => numa::log_placement(&opt.sys_root, cpus, config.num_threads().into()),
+        None => numa::warn_unpinned(&opt.sys_root),
+    }
+    let mut miner_manager = MinerManager::new(config, shutdown.clone());
+    if opt.idle_aware {
+        let idle_config = IdleConfig {
+            action: opt.idle_action,
+            threshold: opt.idle_threshold,
+            quiet_period: Duration::from_secs(opt.idle_quiet_period),
+            proc_root: opt.proc_root.clone(),
+        };
+        info!("Idle-aware mining enabled, will {:?} when other workloads need the CPU", idle_config.action);
+        tokio::spawn(idle::watch(idle_config, miner_manager.governor()));
+    }
+    if let Some(schedule) = opt.schedule.clone() {
+        info!("Mining only during: {}", schedule);
+        tokio::spawn(schedule::watch(schedule, miner_manager.governor()));
+    }
+    if let Some(max_temp) = opt.max_temp {
+        let thermal_config =
+            ThermalConfig { max_temp, hysteresis: opt.temp_hysteresis, sys_root: opt.sys_root.clone() };
+        info!("Throttling mining above {:.1}°C", max_temp);
+        tokio::spawn(thermal::watch(thermal_config, miner_manager.governor(), miner_manager.stats()));
+    }
+    if opt.on_battery.is_some() || opt.min_battery.is_some() {
+        let battery_config =
+            BatteryConfig { action: opt.on_battery, min_capacity: opt.min_battery, sys_root: opt.sys_root.clone() };
+        info!("Limiting mining on battery power, watching the power supplies");
+        tokio::spawn(power::watch(battery_config, miner_manager.governor(), miner_manager.stats()));
+    }
+    if !opt.pause_while.is_empty() {
+        let process_config = ProcessConfig {
+            patterns: opt.pause_while.clone(),
+            action: opt.process_action,
+            proc_root: opt.proc_root.clone(),
+        };
+        let patterns = process_config.patterns.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+        info!("Backing off while any of these processes runs: {}", patterns);
+        tokio::spawn(processes::watch(process_config, miner_manager.governor(), miner_manager.stats()));
+    }
+    let dry_run = opt.dry_run.then(|| DryRun { file: opt.dry_run_file.clone() });
+    if let Some(dry_run) = &dry_run {
+        match &dry_run.file {
+            Some(file) => warn!("Dry run, found blocks won't be submitted but written to {}", file.display()),
+            None => warn!("Dry run, found blocks won't be submitted but only logged"),
         }
+    }
+    let mut control = control::listen_signals()?;
+    while !shutdown.is_shutdown() {
+        let mut client = VecnodHandler::connect(
+            opt.vecnod_address.clone(),
+            pay_addresses.clone(),
+            opt.mine_when_not_synced,
+            dry_run.clone(),
+        )
+        .await?;
         client.client_send(NotifyNewBlockTemplateRequestMessage {}).await?;
         client.client_get_block_template().await?;
 
-        let mut miner_manager =
-            MinerManager::new(client.send_channel.clone(), opt.num_threads, throttle, shutdown.clone());
-        client.listen(&mut miner_manager, shutdown.clone()).await?;
+        miner_manager.connect(client.submit_channel.clone());
+        client.listen(&mut miner_manager, &mut control, shutdown.clone()).await?;
+        miner_manager.disconnect();
         warn!("Disconnected from vecnod, retrying");
     }
     Ok(())
@@ -1,41 +1,124 @@
 use crate::{
+    affinity,
+    client::Submission,
+    control::Control,
+    estimate::{self, Effort, Estimate},
+    governor::Governor,
+    groups::{ThreadGroup, ThreadGroups},
     pow,
-    proto::{RpcBlock, VecnodMessage},
-    swap_rust::WatchSwap,
+    priority::{self, Priority},
+    proto::RpcBlock,
+    stale::StalePolicy,
+    stats::{MinerStats, ThreadRates, ThreadStats},
+    swap_rust::{WaitTimeoutError, WatchSwap},
+    throttle::{DutyCycle, Throttle},
     Error, ShutdownHandler,
 };
-use log::{info, warn};
+use arc_swap::ArcSwapOption;
+use log::{debug, error, info, warn};
 use rand::{thread_rng, RngCore};
 use std::{
+    any::Any,
     num::Wrapping,
     sync::{
-        atomic::{AtomicU64, AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tokio::{
-    sync::mpsc::Sender,
+    sync::mpsc::UnboundedSender,
     task::{self, JoinHandle},
     time::MissedTickBehavior,
 };
 
 type MinerHandler = std::thread::JoinHandle<Result<(), Error>>;
+/// The block submission queue of the current vecnod connection, empty while disconnected.
+/// Shared with the mining threads so they survive reconnects.
+type SubmitChannel = Arc<ArcSwapOption<UnboundedSender<Submission>>>;
+
+struct MinerThread {
+    handle: MinerHandler,
+    // Stops only this thread, used when shrinking the pool
+    stop: ShutdownHandler,
+    stats: Arc<ThreadStats>,
+    // When the thread started mining, after its restart delay
+    started: Instant,
+    // Restarts in a row that failed quickly, for the backoff
+    failures: u32,
+}
 
 #[allow(dead_code)]
 pub struct MinerManager {
-    handles: Vec<MinerHandler>,
-    block_channel: WatchSwap<pow::State>,
-    send_channel: Sender<VecnodMessage>,
-    logger_handle: JoinHandle<()>,
+    threads: Vec<MinerThread>,
+    // Threads that were asked to stop but haven't exited yet
+    stopping: Vec<MinerThread>,
+    config: MinerConfig,
+    shutdown: ShutdownHandler,
+    limits: Arc<Throttle>,
+    governor: Arc<Governor>,
+    groups: Arc<ThreadGroups>,
+    // A template channel for every group
+    block_channels: Vec<WatchSwap<pow::State>>,
+    submit_channel: SubmitChannel,
+    // `None` when the hashrate isn't logged
+    logger_handle: Option<JoinHandle<()>>,
     is_synced: bool,
-    hashes_tried: Arc<AtomicU64>,
+    stats: Arc<MinerStats>,
+    // Compact target of the current template, 0 when there is none
+    current_bits: Arc<AtomicU32>,
     current_state_id: AtomicUsize,
 }
 
 impl Drop for MinerManager {
     fn drop(&mut self) {
-        self.logger_handle.abort();
+        if let Some(logger_handle) = &self.logger_handle {
+            logger_handle.abort();
+        }
+        self.block_channels.iter().for_each(WatchSwap::close);
+        for thread in self.threads.drain(..).chain(self.stopping.drain(..)) {
+            match thread.handle.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("Mining thread failed: {}", e),
+                Err(_) => warn!("Mining thread panicked"),
+            }
+        }
+    }
+}
+
+/// Settings of the mining threads
+#[derive(Debug, Clone, Default)]
+pub struct MinerConfig {
+    pub n_cpus: Option<u16>,
+    /// Minimum time per hash of every thread, kept by resting like the other caps
+    pub throttle: Option<Duration>,
+    /// CPUs to pin the threads to, assigned round robin
+    pub cpus: Option<Vec<usize>>,
+    pub priority: Option<Priority>,
+    /// Percentage of CPU time every thread may use
+    pub max_cpu_percent: Option<f64>,
+    /// Total hashrate cap, split between the threads
+    pub max_hashrate: Option<f64>,
+    /// Whether blocks found on replaced templates are submitted
+    pub stale_policy: StalePolicy,
+    /// Pay addresses sharing the threads, a single group when empty
+    pub groups: Vec<ThreadGroup>,
+    /// Skips the periodic hashrate log, for benchmark runs
+    pub quiet: bool,
+}
+
+impl MinerConfig {
+    pub fn num_threads(&self) -> u16 {
+        // When pinning without an explicit thread count, run a thread per pinned CPU
+        match (self.n_cpus, &self.cpus) {
+            (None, Some(cpus)) => cpus.len().try_into().expect("Doesn't make sense to have more than 65,536 CPU cores"),
+            _ => get_num_cpus(self.n_cpus),
+        }
+    }
+
+    /// By slot, as thread ids aren't reused when the pool is resized
+    fn cpu_for_slot(&self, slot: usize) -> Option<usize> {
+        self.cpus.as_ref().map(|cpus| cpus[slot % cpus.len()])
     }
 }
 
@@ -46,61 +129,182 @@ pub fn get_num_cpus(n_cpus: Option<u16>) -> u16 {
 }
 
 const LOG_RATE: Duration = Duration::from_secs(10);
+// How often idle threads wake up to check for shutdown
+const IDLE_WAIT: Duration = Duration::from_secs(1);
+// How often parked threads check if they can resume
+const PARK_WAIT: Duration = Duration::from_millis(100);
+// Delay before restarting a dead thread, doubled on every failure in a row
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+// A thread that ran this long before dying restarts without backoff
+const BACKOFF_RESET: Duration = Duration::from_secs(5 * 60);
+
+fn restart_delay(failures: u32) -> Duration {
+    RESTART_BACKOFF.saturating_mul(1 << failures.saturating_sub(1).min(16)).min(MAX_RESTART_BACKOFF)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message,
+        _ => "unknown panic payload",
+    }
+}
 
 impl MinerManager {
-    pub fn new(
-        send_channel: Sender<VecnodMessage>,
-        n_cpus: Option<u16>,
-        throttle: Option<Duration>,
-        shutdown: ShutdownHandler,
-    ) -> Self {
-        let hashes_tried = Arc::new(AtomicU64::new(0));
-        let watch = WatchSwap::empty();
-        let handles = Self::launch_cpu_threads(
-            send_channel.clone(),
-            hashes_tried.clone(),
-            watch.clone(),
+    pub fn new(config: MinerConfig, shutdown: ShutdownHandler) -> Self {
+        let submit_channel = SubmitChannel::default();
+        let stats = Arc::new(MinerStats::default());
+        let current_bits = Arc::new(AtomicU32::new(0));
+        let groups = Arc::new(ThreadGroups::new(&config.groups));
+        let block_channels = (0..groups.len()).map(|_| WatchSwap::empty()).collect();
+        let num_threads = config.num_threads();
+        if let Some(priority) = config.priority {
+            info!("Mining threads will run with {} priority", priority);
+        }
+        if let Some(max_cpu_percent) = config.max_cpu_percent {
+            info!("Limiting mining threads to {:.1}% CPU", max_cpu_percent);
+        }
+        if let Some(max_hashrate) = config.max_hashrate {
+            let (rate, suffix) = Self::hash_suffix(max_hashrate);
+            info!("Capping hashrate at {:.2} {}", rate, suffix);
+        }
+        let limits = Arc::new(Throttle::new(config.max_cpu_percent, config.max_hashrate));
+        limits.set_hash_interval(config.throttle);
+        let governor = Arc::new(Governor::default());
+        let quiet = config.quiet;
+
+        let mut manager = Self {
+            threads: Vec::new(),
+            stopping: Vec::new(),
+            config,
             shutdown,
-            n_cpus,
-            throttle,
-        )
-        .collect();
-
-        Self {
-            handles,
-            block_channel: watch,
-            send_channel,
-            logger_handle: task::spawn(Self::log_hashrate(Arc::clone(&hashes_tried))),
+            limits,
+            governor: Arc::clone(&governor),
+            groups: Arc::clone(&groups),
+            block_channels,
+            submit_channel,
+            logger_handle: (!quiet).then(|| {
+                task::spawn(Self::log_hashrate(Arc::clone(&stats), Arc::clone(&current_bits), governor, groups))
+            }),
             is_synced: true,
-            hashes_tried,
+            stats,
+            current_bits,
             current_state_id: AtomicUsize::new(0),
+        };
+        info!("Launching: {} cpu miners", num_threads);
+        manager.set_threads(num_threads);
+        manager
+    }
+
+    /// Grows or shrinks the pool while mining, keeping the current template.
+    /// Removed threads exit at their next shutdown check, and their hashes are still counted.
+    pub fn set_threads(&mut self, num_threads: u16) {
+        self.reap_stopped_threads();
+        let num_threads = num_threads as usize;
+        while self.threads.len() < num_threads {
+            let thread = self.launch_cpu_miner(self.threads.len(), self.stats.register_thread(), Duration::ZERO);
+            self.threads.push(thread);
+        }
+        while self.threads.len() > num_threads {
+            let thread = self.threads.pop().expect("checked above");
+            let _stop = thread.stop.arm();
+            self.stopping.push(thread);
         }
+        self.limits.set_num_threads(self.threads.len());
+        self.governor.set_pool_size(self.threads.len());
     }
 
-    fn launch_cpu_threads(
-        send_channel: Sender<VecnodMessage>,
-        hashes_tried: Arc<AtomicU64>,
-        work_channel: WatchSwap<pow::State>,
-        shutdown: ShutdownHandler,
-        n_cpus: Option<u16>,
-        throttle: Option<Duration>,
-    ) -> impl Iterator<Item = MinerHandler> {
-        let n_cpus = get_num_cpus(n_cpus);
-        info!("Launching: {} cpu miners", n_cpus);
-        (0..n_cpus).map(move |_| {
-            Self::launch_cpu_miner(
-                send_channel.clone(),
-                work_channel.clone(),
-                hashes_tried.clone(),
-                throttle,
-                shutdown.clone(),
-            )
-        })
-    }
-
-    pub fn process_block(&mut self, block: Option<RpcBlock>) -> Result<(), Error> {
+    pub fn handle_control(&mut self, control: Control) {
+        let current = self.num_threads() as u16;
+        let num_threads = match control {
+            Control::AddThread => current.saturating_add(1),
+            Control::RemoveThread => current.saturating_sub(1).max(1),
+        };
+        if num_threads != current {
+            info!("Changing the number of mining threads from {} to {}", current, num_threads);
+            self.set_threads(num_threads);
+        }
+    }
+
+    /// Limits the active threads, for watchers of the machine's state
+    pub fn governor(&self) -> Arc<Governor> {
+        Arc::clone(&self.governor)
+    }
+
+    pub fn stats(&self) -> Arc<MinerStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Amount of mining threads in the pool
+    pub fn num_threads(&self) -> usize {
+        self.threads.len()
+    }
+
+    fn reap_stopped_threads(&mut self) {
+        let (finished, stopping) = self.stopping.drain(..).partition(|thread| thread.handle.is_finished());
+        self.stopping = stopping;
+        for thread in finished {
+            if let Ok(Err(e)) = thread.handle.join() {
+                warn!("Mining thread #{} failed: {}", thread.stats.id, e);
+            }
+            self.stats.unregister_thread(&thread.stats);
+        }
+    }
+
+    /// Restarts mining threads that died, waiting longer for threads that keep failing
+    pub fn supervise(&mut self) {
+        if self.shutdown.is_shutdown() {
+            return;
+        }
+        for slot in 0..self.threads.len() {
+            let thread = &self.threads[slot];
+            if !thread.handle.is_finished() {
+                continue;
+            }
+            let failures = if thread.started.elapsed() < BACKOFF_RESET { thread.failures + 1 } else { 1 };
+            let delay = restart_delay(failures);
+            let replacement = self.launch_cpu_miner(slot, Arc::clone(&thread.stats), delay);
+            let dead = std::mem::replace(&mut self.threads[slot], MinerThread { failures, ..replacement });
+            let id = dead.stats.id;
+            match dead.handle.join() {
+                Ok(Ok(())) => warn!("Mining thread #{} exited unexpectedly", id),
+                Ok(Err(e)) => error!("Mining thread #{} failed: {}", id, e),
+                Err(payload) => error!("Mining thread #{} panicked: {}", id, panic_message(&*payload)),
+            }
+            warn!("Restarting mining thread #{} in {:?}", id, delay);
+            self.stats.add_thread_restart();
+        }
+    }
+
+    /// Hands a new vecnod connection to the mining threads, they resume once the first template arrives.
+    pub fn connect(&mut self, submit_channel: UnboundedSender<Submission>) {
+        self.submit_channel.store(Some(Arc::new(submit_channel)));
+    }
+
+    /// Pauses the mining threads until the next connection, without stopping them.
+    pub fn disconnect(&mut self) {
+        self.submit_channel.store(None);
+        self.current_bits.store(0, Ordering::Relaxed);
+        self.is_synced = true;
+        self.block_channels.iter().for_each(|channel| drop(channel.swap(None)));
+        self.reap_stopped_threads();
+        self.supervise();
+    }
+
+    /// Amount of mining threads that are still running
+    #[cfg(all(test, not(feature = "shuttle")))]
+    pub fn running_threads(&self) -> usize {
+        self.threads.iter().filter(|thread| !thread.handle.is_finished()).count()
+    }
+
+    /// Hands a template to the threads of a group, `None` when vecnod isn't synced pauses all the groups
+    pub fn process_block(&mut self, group: usize, block: Option<RpcBlock>) -> Result<(), Error> {
         let state = if let Some(b) = block {
             self.is_synced = true;
+            if let Some(header) = &b.header {
+                self.current_bits.store(header.bits, Ordering::Relaxed);
+            }
             // Relaxed ordering here means there's no promise that the counter will always go up, but the id will always be unique
             let id = self.current_state_id.fetch_add(1, Ordering::Relaxed);
             Some(pow::State::new(id, b)?)
@@ -110,83 +314,331 @@ impl MinerManager {
             }
             self.is_synced = false;
             warn!("Vecnod is not synced, skipping current template");
-            None
+            // No template is mined, so there's no difficulty to estimate with either
+            self.current_bits.store(0, Ordering::Relaxed);
+            self.block_channels.iter().for_each(|channel| drop(channel.swap(None)));
+            self.reap_stopped_threads();
+            return Ok(());
         };
 
-        self.block_channel.swap(state);
+        self.block_channels.get(group).ok_or("Template for an unknown group")?.swap(state);
+        self.reap_stopped_threads();
         Ok(())
     }
 
-    pub fn launch_cpu_miner(
-        send_channel: Sender<VecnodMessage>,
-        mut block_channel: WatchSwap<pow::State>,
-        hashes_tried: Arc<AtomicU64>,
-        throttle: Option<Duration>,
-        shutdown: ShutdownHandler,
-    ) -> MinerHandler {
+    /// Spawns a mining thread for a slot of the pool, which starts mining after `start_delay`
+    fn launch_cpu_miner(&self, slot: usize, thread_stats: Arc<ThreadStats>, start_delay: Duration) -> MinerThread {
         // We mark it cold as the function is not called often, and it's not in the hot path
         #[cold]
-        fn found_block(send_channel: &Sender<VecnodMessage>, block: RpcBlock) -> Result<(), Error> {
-            let block_hash = block.block_hash().expect("We just got it from the state, we should be able to hash it");
-            send_channel.blocking_send(VecnodMessage::submit_block(block))?;
-            info!("Found a block: {:x}", block_hash);
+        fn found_block(
+            submit_channel: &SubmitChannel,
+            stats: &MinerStats,
+            stale_policy: StalePolicy,
+            address: &str,
+            block: RpcBlock,
+            template_id: usize,
+            stale_for: Option<Duration>,
+        ) -> Result<(), Error> {
+            // An invalid block can get us banned by the node, so it's worth a second hash
+            let block_hash = match pow::verify_block(&block) {
+                Ok(block_hash) => block_hash,
+                Err(e) => {
+                    error!("Hardware error, dropping a block that failed verification: {}", e);
+                    stats.add_hardware_error();
+                    return Ok(());
+                }
+            };
+            if let Some(stale_for) = stale_for {
+                let submit = stale_policy.should_submit(Some(stale_for));
+                stats.add_stale_solution(submit);
+                if !submit {
+                    warn!(
+                        "Found a block on template #{} replaced {:.2?} earlier, dropping it: {:x}",
+                        template_id, stale_for, block_hash
+                    );
+                    return Ok(());
+                }
+            }
+            // Never blocks, and a failed send means the connection was lost, the thread should keep going for the next connection
+            match submit_channel.load_full() {
+                Some(sender) if sender.send(Submission::new(block, template_id, stale_for)).is_ok() => {
+                    stats.add_block_found();
+                    match address {
+                        "" => info!("Found a block: {:x}", block_hash),
+                        address => info!("Found a block for {}: {:x}", address, block_hash),
+                    }
+                }
+                _ => warn!("Found a block while disconnected from vecnod, dropping it: {:x}", block_hash),
+            }
             Ok(())
         }
 
         let mut nonce = Wrapping(thread_rng().next_u64());
-        std::thread::spawn(move || {
+        let stats = Arc::clone(&self.stats);
+        let submit_channel = Arc::clone(&self.submit_channel);
+        let mut block_channels = self.block_channels.clone();
+        let groups = Arc::clone(&self.groups);
+        let shutdown = self.shutdown.clone();
+        let stop = ShutdownHandler(Arc::new(AtomicBool::new(false)));
+        let stale_policy = self.config.stale_policy;
+        let cpu = self.config.cpu_for_slot(slot);
+        let thread_priority = self.config.priority;
+        let mut duty_cycle = DutyCycle::new(Arc::clone(&self.limits));
+        let governor = Arc::clone(&self.governor);
+        let started = Instant::now() + start_delay;
+        let (thread_stats_clone, stop_clone) = (Arc::clone(&thread_stats), stop.clone());
+        let handle = std::thread::spawn(move || {
+            let (thread_stats, stop) = (thread_stats_clone, stop_clone);
+            while Instant::now() < started {
+                if shutdown.is_shutdown() || stop.is_shutdown() || block_channels[0].is_closed() {
+                    return Ok(());
+                }
+                std::thread::sleep(PARK_WAIT.min(started.saturating_duration_since(Instant::now())));
+            }
+            duty_cycle.resume();
+            if let Some(cpu) = cpu {
+                match affinity::pin_current_thread(cpu) {
+                    Ok(()) => info!("Pinned mining thread #{} to CPU {}", thread_stats.id, cpu),
+                    Err(e) => warn!("Failed pinning mining thread #{} to CPU {}: {}", thread_stats.id, cpu, e),
+                }
+            }
+            if let Some(thread_priority) = thread_priority {
+                match priority::set_current_thread_priority(thread_priority) {
+                    Ok(()) => debug!("Set mining thread #{} to {} priority", thread_stats.id, thread_priority),
+                    Err(e) => warn!(
+                        "Failed setting mining thread #{} to {} priority: {}",
+                        thread_stats.id, thread_priority, e
+                    ),
+                }
+            }
+            let mut group = groups.group_of(slot, governor.active_threads());
             let mut state = None;
             loop {
                 if state.is_none() {
-                    state = block_channel.wait_for_change().as_deref().cloned();
+                    match block_channels[group].wait_for_change_timeout(IDLE_WAIT) {
+                        // Shares the block, but copies the hashing state onto this thread, after it was pinned
+                        Ok(new_state) => {
+                            state = new_state.as_deref().cloned();
+                            duty_cycle.resume();
+                        }
+                        Err(WaitTimeoutError::Timeout) if !shutdown.is_shutdown() && !stop.is_shutdown() => {}
+                        Err(_) => return Ok(()),
+                    }
                 }
                 let Some(state_ref) = state.as_mut() else {
+                    // The thread may have been dealt to a group that already has a template
+                    group = groups.group_of(slot, governor.active_threads());
+                    state = block_channels[group].get_latest().as_deref().cloned();
+                    duty_cycle.resume();
                     continue;
                 };
                 state_ref.nonce = nonce.0;
 
                 if let Some(block) = state_ref.generate_block_if_pow() {
-                    found_block(&send_channel, block)?;
+                    // The thread may not have picked up a newer template yet
+                    let stale_for = match block_channels[group].latest() {
+                        Some(latest) if latest.id() == state_ref.id() => None,
+                        Some(latest) => Some(latest.age()),
+                        // Withdrawn as vecnod isn't synced, at most a hash ago since changes are checked on every hash
+                        None => Some(Duration::ZERO),
+                    };
+                    found_block(
+                        &submit_channel,
+                        &stats,
+                        stale_policy,
+                        groups.address(group),
+                        block,
+                        state_ref.id(),
+                        stale_for,
+                    )?;
                 }
                 nonce += Wrapping(1);
 
-                if nonce.0 % 128 == 0 {
-                    hashes_tried.fetch_add(128, Ordering::Relaxed);
-                    if shutdown.is_shutdown() {
-                        return Ok(());
-                    }
-                    if let Some(new_state) = block_channel.get_changed() {
+                // Checked on every hash so a new template preempts the stale one right away
+                if block_channels[group].has_changed() {
+                    if let Some(new_state) = block_channels[group].get_changed() {
+                        if let Some(new_state) = new_state.as_deref() {
+                            stats.add_stale_work(new_state.age());
+                        }
                         state = new_state.as_deref().cloned();
                     }
                 }
 
-                if let Some(sleep_duration) = throttle {
-                    std::thread::sleep(sleep_duration)
+                if nonce.0 % 128 == 0 {
+                    thread_stats.add_hashes(128);
+                    groups.add_hashes(group, 128);
+                    duty_cycle.batch_done(128, || shutdown.is_shutdown() || stop.is_shutdown());
+                    let new_group = groups.group_of(slot, governor.active_threads());
+                    if new_group != group {
+                        group = new_group;
+                        state = block_channels[group].get_latest().as_deref().cloned();
+                    }
+                    let mut parked = false;
+                    while !governor.is_active(slot)
+                        && !shutdown.is_shutdown()
+                        && !stop.is_shutdown()
+                        && !block_channels[group].is_closed()
+                    {
+                        thread_stats.mark_parked();
+                        std::thread::sleep(PARK_WAIT);
+                        parked = true;
+                    }
+                    if parked {
+                        duty_cycle.resume();
+                    }
+                    if shutdown.is_shutdown() || stop.is_shutdown() || block_channels[group].is_closed() {
+                        return Ok(());
+                    }
                 }
             }
-        })
+        });
+        MinerThread { handle, stop, stats: thread_stats, started, failures: 0 }
     }
 
-    async fn log_hashrate(hashes_tried: Arc<AtomicU64>) {
+    async fn log_hashrate(
+        stats: Arc<MinerStats>,
+        current_bits: Arc<AtomicU32>,
+        governor: Arc<Governor>,
+        groups: Arc<ThreadGroups>,
+    ) {
         let mut ticker = tokio::time::interval(LOG_RATE);
         ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
         let mut last_instant = ticker.tick().await;
+        let mut last_paused = governor.paused_time();
+        let mut effort = Effort::default();
+        let mut last_blocks_found = 0;
+        let mut last_restarts = 0;
+        let mut last_hardware_errors = 0;
+        let mut last_stale_solutions = (0, 0);
+        let mut last_pause_transitions = Vec::new();
         for i in 0u64.. {
             let now = ticker.tick().await;
-            let hashes = hashes_tried.swap(0, Ordering::Relaxed);
-            let rate = (hashes as f64) / (now - last_instant).as_secs_f64();
-            if hashes == 0 && i % 2 == 0 {
+            // Time spent paused doesn't count towards the hashrate
+            let paused = governor.paused_time();
+            let secs = (now - last_instant).saturating_sub(paused.saturating_sub(last_paused)).as_secs_f64().max(0.001);
+            let thread_hashes = stats.take_thread_hashes();
+            // Includes the hashes of threads that were removed during this period
+            let hashes = thread_hashes.iter().map(|&(_, hashes)| hashes).sum::<u64>() + stats.take_retired_hashes();
+            let thread_rates = ThreadRates::new(&thread_hashes, secs);
+            let rate = hashes as f64 / secs;
+            let expected_hashes = match current_bits.load(Ordering::Relaxed) {
+                0 => f64::INFINITY,
+                bits => estimate::expected_hashes_from_bits(bits),
+            };
+            let found = stats.blocks_found();
+            if found != last_blocks_found {
+                // Before this period's hashes, as some of them were mined after the block
+                info!("Found {} block(s) with {:.2}% effort", found - last_blocks_found, effort.block_found() * 100.0);
+                last_blocks_found = found;
+            }
+            effort.add_hashes(hashes, expected_hashes);
+            let restarts = stats.thread_restarts();
+            if restarts != last_restarts {
+                warn!("Mining threads were restarted {} time(s) so far", restarts);
+                last_restarts = restarts;
+            }
+            let hardware_errors = stats.hardware_errors();
+            if hardware_errors != last_hardware_errors {
+                warn!(
+                    "{} found block(s) failed verification so far, the CPU or memory may be unstable",
+                    hardware_errors
+                );
+                last_hardware_errors = hardware_errors;
+            }
+            let stale_solutions = stats.stale_solutions();
+            if stale_solutions != last_stale_solutions {
+                info!(
+                    "Stale blocks so far: {} submitted, {} dropped by the stale policy",
+                    stale_solutions.0, stale_solutions.1
+                );
+                last_stale_solutions = stale_solutions;
+            }
+            let pause_transitions = stats.pause_transitions();
+            if pause_transitions != last_pause_transitions {
+                for (source, transitions) in &pause_transitions {
+                    info!(
+                        "Mining was paused {} time(s), limited {} time(s) and resumed {} time(s) because of {} so far",
+                        transitions.pauses, transitions.limits, transitions.resumes, source
+                    );
+                }
+                last_pause_transitions = pause_transitions;
+            }
+            let limits = governor.limits().iter().map(|(source, _)| source.to_string()).collect::<Vec<_>>().join(", ");
+            if governor.is_paused() {
+                if i % 2 == 0 {
+                    info!("Mining is paused because of {}", limits);
+                }
+            } else if hashes == 0 && i % 2 == 0 {
                 warn!("Vecnod is still not synced");
+            } else if paused != last_paused {
+                // The few seconds mined since resuming would give a noisy rate, the next period gets a full one
+                info!("Mining resumed");
             } else if hashes != 0 {
-                let (rate, suffix) = Self::hash_suffix(rate);
-                info!("Current hashrate is: {:.2} {}", rate, suffix);
+                let (rate_display, suffix) = Self::hash_suffix(rate);
+                match governor.limits().is_empty() {
+                    true => info!("Current hashrate is: {:.2} {}", rate_display, suffix),
+                    false => info!(
+                        "Current hashrate is: {:.2} {} ({} threads active, limited by {})",
+                        rate_display,
+                        suffix,
+                        governor.active_threads(),
+                        limits
+                    ),
+                }
+                Self::log_thread_rates(&thread_rates);
+                if groups.len() > 1 {
+                    let split = groups
+                        .hash_split()
+                        .iter()
+                        .enumerate()
+                        .map(|(group, share)| format!("{:.1}% to {}", share * 100.0, groups.address(group)))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    info!("Hashes so far: {}", split);
+                }
+                if let Some(estimate) = Estimate::new(expected_hashes, rate) {
+                    info!("{}, effort since last block: {:.2}%", estimate, effort.current() * 100.0);
+                }
+            }
+            let stale = stats.take_stale_work();
+            if let Some(average) = stale.average() {
+                info!(
+                    "Threads kept mining replaced templates for {:.2?} on average, {:.2?} at most ({} switches)",
+                    average, stale.max, stale.switches
+                );
+            }
+            let temperatures = stats.temperatures();
+            if !temperatures.is_empty() {
+                let temperatures = temperatures.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+                info!("Temperatures: {}", temperatures);
+            }
+            if let Some(power) = stats.power() {
+                info!("Power: {}", power);
             }
             last_instant = now;
+            last_paused = paused;
+        }
+    }
+
+    fn log_thread_rates(thread_rates: &ThreadRates) {
+        if log::log_enabled!(log::Level::Debug) {
+            let rates: Vec<String> = thread_rates
+                .0
+                .iter()
+                .map(|&(id, rate)| {
+                    let (rate, suffix) = Self::hash_suffix(rate);
+                    format!("#{}: {:.2} {}", id, rate, suffix)
+                })
+                .collect();
+            debug!("Thread hashrates: {}", rates.join(", "));
+        }
+        for (id, ratio) in thread_rates.slow_threads() {
+            warn!("Thread #{} is mining at {:.0}% of the median thread hashrate", id, ratio * 100.0);
         }
     }
 
     #[inline]
-    fn hash_suffix(n: f64) -> (f64, &'static str) {
+    pub fn hash_suffix(n: f64) -> (f64, &'static str) {
         match n {
             n if n < 1_000.0 => (n, "hash/s"),
             n if n < 1_000_000.0 => (n / 1_000.0, "Khash/s"),
@@ -198,46 +650,182 @@ impl MinerManager {
     }
 }
 
+// The mining threads need real threads, which shuttle's primitives don't support outside of its scheduler
+#[cfg(all(test, not(feature = "shuttle")))]
+pub(crate) mod tests {
+    use super::{MinerConfig, MinerManager, MinerThread};
+    use crate::bench::synthetic_block;
+    use crate::groups::ThreadGroup;
+    use crate::stats::ThreadStats;
+    use crate::ShutdownHandler;
+    use std::sync::{atomic::AtomicBool, Arc};
+    use std::time::{Duration, Instant};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_threads_survive_reconnects() {
+        let shutdown = ShutdownHandler(Arc::new(AtomicBool::new(false)));
+        let _shutdown_when_dropped = shutdown.arm();
+        let config = MinerConfig { n_cpus: Some(3), ..Default::default() };
+        let mut manager = MinerManager::new(config, shutdown);
+        for _ in 0..50 {
+            let (submit_channel, _recv) = mpsc::unbounded_channel();
+            manager.connect(submit_channel);
+            // Target of 2^224, so the threads practically never find a block
+            manager.process_block(0, Some(synthetic_block(0x1d00ffff))).unwrap();
+            manager.disconnect();
+            assert_eq!(manager.running_threads(), 3);
+        }
+        assert_eq!(manager.threads.len(), 3);
+
+        let (submit_channel, _recv) = mpsc::unbounded_channel();
+        manager.connect(submit_channel);
+        manager.process_block(0, Some(synthetic_block(0x1d00ffff))).unwrap();
+        let start = Instant::now();
+        while manager.stats.take_thread_hashes().iter().all(|&(_, hashes)| hashes == 0) {
+            assert!(start.elapsed() < Duration::from_secs(30), "Threads didn't resume mining after reconnecting");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(manager.running_threads(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_set_threads() {
+        let shutdown = ShutdownHandler(Arc::new(AtomicBool::new(false)));
+        let _shutdown_when_dropped = shutdown.arm();
+        let config = MinerConfig { n_cpus: Some(2), ..Default::default() };
+        let mut manager = MinerManager::new(config, shutdown);
+        manager.process_block(0, Some(synthetic_block(0x1d00ffff))).unwrap();
+        manager.set_threads(5);
+        assert_eq!((manager.num_threads(), manager.running_threads()), (5, 5));
+        assert_eq!(manager.stats.take_thread_hashes().len(), 5);
+
+        manager.set_threads(1);
+        assert_eq!(manager.num_threads(), 1);
+        let start = Instant::now();
+        while !manager.stopping.is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(30), "Removed threads didn't exit");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            manager.reap_stopped_threads();
+        }
+        assert_eq!(manager.running_threads(), 1);
+        // Only the remaining thread is still reported, the removed threads were folded into the retired hashes
+        assert_eq!(manager.stats.take_thread_hashes().iter().map(|&(id, _)| id).collect::<Vec<_>>(), vec![0]);
+        // The current template is kept
+        let mut block_channel = manager.block_channels[0].clone();
+        assert!(matches!(block_channel.get_changed().as_deref(), Some(Some(_))));
+    }
+
+    #[tokio::test]
+    async fn test_resize_keeps_cpus_distinct() {
+        let shutdown = ShutdownHandler(Arc::new(AtomicBool::new(false)));
+        let _shutdown_when_dropped = shutdown.arm();
+        let config = MinerConfig { cpus: Some(vec![4, 5, 6]), ..Default::default() };
+        let mut manager = MinerManager::new(config, shutdown);
+        manager.set_threads(1);
+        manager.set_threads(3);
+        // The new threads get fresh ids, which would wrap around onto the CPU of the remaining one
+        let ids: Vec<usize> = manager.threads.iter().map(|thread| thread.stats.id).collect();
+        assert_eq!(ids, [0, 3, 4]);
+        let cpus: Vec<_> = (0..manager.threads.len()).map(|slot| manager.config.cpu_for_slot(slot)).collect();
+        assert_eq!(cpus, [Some(4), Some(5), Some(6)]);
+    }
+
+    #[tokio::test]
+    async fn test_thread_groups() {
+        let shutdown = ShutdownHandler(Arc::new(AtomicBool::new(false)));
+        let _shutdown_when_dropped = shutdown.arm();
+        let groups = ["a", "b"].map(|address| ThreadGroup { address: address.to_owned(), share: 0.5 }).to_vec();
+        let config = MinerConfig { n_cpus: Some(4), groups, ..Default::default() };
+        let mut manager = MinerManager::new(config, shutdown);
+        manager.process_block(0, Some(synthetic_block(0x1d00ffff))).unwrap();
+        manager.process_block(1, Some(synthetic_block(0x1d00ffff))).unwrap();
+        assert!(manager.process_block(2, Some(synthetic_block(0x1d00ffff))).is_err());
+        let start = Instant::now();
+        while manager.groups.hash_split().contains(&0.0) {
+            assert!(start.elapsed() < Duration::from_secs(30), "Both groups should be mined at once");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_restarts_dead_threads() {
+        let shutdown = ShutdownHandler(Arc::new(AtomicBool::new(false)));
+        let _shutdown_when_dropped = shutdown.arm();
+        let config = MinerConfig { n_cpus: Some(2), ..Default::default() };
+        let mut manager = MinerManager::new(config, shutdown.clone());
+        manager.process_block(0, Some(synthetic_block(0x1d00ffff))).unwrap();
+
+        // Swap the first thread for one that panics
+        let stats = Arc::clone(&manager.threads[0].stats);
+        let panicking = MinerThread {
+            handle: std::thread::spawn(|| panic!("test panic")),
+            stop: ShutdownHandler(Arc::new(AtomicBool::new(false))),
+            stats: Arc::clone(&stats),
+            started: Instant::now(),
+            failures: 0,
+        };
+        let original = std::mem::replace(&mut manager.threads[0], panicking);
+        drop(original.stop.arm());
+        original.handle.join().unwrap().unwrap();
+        while !manager.threads[0].handle.is_finished() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        manager.supervise();
+        assert_eq!(manager.stats.thread_restarts(), 1);
+        assert_eq!(manager.running_threads(), 2);
+        assert_eq!(manager.threads[0].failures, 1);
+        // The restarted thread keeps its counters
+        assert!(Arc::ptr_eq(&manager.threads[0].stats, &stats));
+        let stats: &ThreadStats = &manager.threads[0].stats;
+        let start = Instant::now();
+        while manager.stats.take_thread_hashes().iter().all(|&(id, hashes)| id != stats.id || hashes == 0) {
+            assert!(start.elapsed() < Duration::from_secs(30), "Restarted thread didn't resume mining");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[test]
+    fn test_restart_delay() {
+        assert_eq!(super::restart_delay(1), Duration::from_secs(1));
+        assert_eq!(super::restart_delay(3), Duration::from_secs(4));
+        assert_eq!(super::restart_delay(100), Duration::from_secs(60));
+        let payload = std::panic::catch_unwind(|| panic!("{}", 42)).unwrap_err();
+        assert_eq!(super::panic_message(&*payload), "42");
+    }
+}
+
 #[cfg(all(test, feature = "bench"))]
 mod benches {
     extern crate test;
 
     use self::test::{black_box, Bencher};
+    use crate::bench::synthetic_block;
     use crate::pow::State;
-    use crate::proto::{RpcBlock, RpcBlockHeader};
+    use crate::proto::RpcTransaction;
     use rand::{thread_rng, RngCore};
 
     #[bench]
     pub fn bench_mining(bh: &mut Bencher) {
-        let mut state = State::new(
-            1,
-            RpcBlock {
-                header: Some(RpcBlockHeader {
-                    version: 1,
-                    parents: vec![],
-                    hash_merkle_root: "23618af45051560529440541e7dc56be27676d278b1e00324b048d410a19d764".to_string(),
-                    accepted_id_merkle_root: "947d1a10378d6478b6957a0ed71866812dee33684968031b1cace4908c149d94"
-                        .to_string(),
-                    utxo_commitment: "ec5e8fc0bc0c637004cee262cef12e7cf6d9cd7772513dbd466176a07ab7c4f4".to_string(),
-                    timestamp: 654654353,
-                    bits: 0x1e7fffff,
-                    nonce: 0,
-                    daa_score: 654456,
-                    blue_work: "d8e28a03234786".to_string(),
-                    pruning_point: "be4c415d378f9113fabd3c09fcc84ddb6a00f900c87cb6a1186993ddc3014e2d".to_string(),
-                    blue_score: 1164419,
-                }),
-                transactions: vec![],
-                verbose_data: None,
-            },
-        )
-        .unwrap();
+        let mut state = State::new(1, synthetic_block(0x1e7fffff)).unwrap();
         state.nonce = thread_rng().next_u64();
         bh.iter(|| {
             for _ in 0..100 {
-                black_box(state.check_pow());
+                black_box(state.check_pow(state.nonce));
                 state.nonce += 1;
             }
         });
     }
+
+    #[bench]
+    pub fn bench_template_switch(bh: &mut Bencher) {
+        // What every mining thread does when a new template arrives, with a template of 5,000 transactions
+        let mut block = synthetic_block(0x1e7fffff);
+        let transaction =
+            RpcTransaction { subnetwork_id: "00".repeat(20), payload: "ab".repeat(500), ..Default::default() };
+        block.transactions = vec![transaction; 5000];
+        let state = Some(State::new(1, block).unwrap());
+        bh.iter(|| black_box(state.as_ref().cloned()));
+    }
 }
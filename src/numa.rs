@@ -0,0 +1,130 @@
+use crate::{affinity::parse_cpu_list, Error};
+use log::{info, warn};
+use std::{fs, path::Path};
+
+/// A NUMA node and its CPUs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumaNode {
+    pub id: usize,
+    pub cpus: Vec<usize>,
+}
+
+/// The NUMA nodes with CPUs according to `sys_root/devices/system/node`, sorted by id.
+/// Memory-only nodes are skipped.
+pub fn numa_nodes(sys_root: &Path) -> Result<Vec<NumaNode>, Error> {
+    let mut nodes = Vec::new();
+    for entry in fs::read_dir(sys_root.join("devices/system/node"))? {
+        let entry = entry?;
+        let Some(Ok(id)) = entry.file_name().to_str().and_then(|name| name.strip_prefix("node")).map(str::parse) else {
+            continue;
+        };
+        let cpus = parse_cpu_list(&fs::read_to_string(entry.path().join("cpulist"))?)?;
+        if !cpus.is_empty() {
+            nodes.push(NumaNode { id, cpus });
+        }
+    }
+    nodes.sort_by_key(|node| node.id);
+    Ok(nodes)
+}
+
+fn node_of(nodes: &[NumaNode], cpu: usize) -> Option<usize> {
+    nodes.iter().find(|node| node.cpus.contains(&cpu)).map(|node| node.id)
+}
+
+/// Reorders the CPUs so that assigning them in order alternates between the nodes,
+/// which spreads any number of threads evenly. CPUs outside of the known nodes go last.
+pub fn interleave(nodes: &[NumaNode], cpus: &[usize]) -> Vec<usize> {
+    let mut per_node: Vec<Vec<usize>> =
+        nodes.iter().map(|node| cpus.iter().copied().filter(|cpu| node.cpus.contains(cpu)).rev().collect()).collect();
+    let mut interleaved = Vec::with_capacity(cpus.len());
+    while per_node.iter().any(|cpus| !cpus.is_empty()) {
+        interleaved.extend(per_node.iter_mut().filter_map(Vec::pop));
+    }
+    interleaved.extend(cpus.iter().copied().filter(|&cpu| node_of(nodes, cpu).is_none()));
+    interleaved
+}
+
+/// How many threads end up on every node, and on which CPUs, when thread `i` is pinned to `cpus[i % cpus.len()]`
+pub fn placement(nodes: &[NumaNode], cpus: &[usize], num_threads: usize) -> Vec<(Option<usize>, Vec<usize>)> {
+    let mut placement: Vec<(Option<usize>, Vec<usize>)> = Vec::new();
+    for cpu in cpus.iter().cycle().take(num_threads) {
+        let node = node_of(nodes, *cpu);
+        match placement.iter_mut().find(|(id, _)| *id == node) {
+            Some((_, node_cpus)) => node_cpus.push(*cpu),
+            None => placement.push((node, vec![*cpu])),
+        }
+    }
+    placement.sort();
+    placement
+}
+
+/// Warns when the machine has several NUMA nodes but the mining threads aren't pinned,
+/// as only `--cpu-affinity auto` spreads them over the nodes
+pub fn warn_unpinned(sys_root: &Path) {
+    let nodes = match numa_nodes(sys_root) {
+        Ok(nodes) if nodes.len() > 1 => nodes,
+        _ => return,
+    };
+    for node in &nodes {
+        info!("NUMA node {}: CPUs {:?}", node.id, node.cpus);
+    }
+    warn!(
+        "Found {} NUMA nodes but the mining threads aren't pinned, pass --cpu-affinity auto to spread them over the nodes",
+        nodes.len()
+    );
+}
+
+/// Logs which NUMA node every mining thread runs on
+pub fn log_placement(sys_root: &Path, cpus: &[usize], num_threads: usize) {
+    let nodes = match numa_nodes(sys_root) {
+        Ok(nodes) if nodes.len() > 1 => nodes,
+        _ => return,
+    };
+    for (node, node_cpus) in placement(&nodes, cpus, num_threads) {
+        match node {
+            Some(node) => info!("NUMA node {}: {} mining thread(s) on CPUs {:?}", node, node_cpus.len(), node_cpus),
+            None => info!("No NUMA node: {} mining thread(s) on CPUs {:?}", node_cpus.len(), node_cpus),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn two_nodes() -> Vec<NumaNode> {
+        vec![NumaNode { id: 0, cpus: vec![0, 1, 2, 3] }, NumaNode { id: 1, cpus: vec![4, 5, 6, 7] }]
+    }
+
+    #[test]
+    fn test_numa_nodes() {
+        let root = TempDir::new().unwrap();
+        let node_dir = root.path().join("devices/system/node");
+        for (node, cpulist) in [("node1", "4-7\n"), ("node0", "0-3\n"), ("node2", "\n")] {
+            fs::create_dir_all(node_dir.join(node)).unwrap();
+            fs::write(node_dir.join(node).join("cpulist"), cpulist).unwrap();
+        }
+        fs::create_dir_all(node_dir.join("power")).unwrap();
+        fs::write(node_dir.join("online"), "0-2\n").unwrap();
+        assert_eq!(numa_nodes(root.path()).unwrap(), two_nodes());
+        assert!(numa_nodes(TempDir::new().unwrap().path()).is_err());
+    }
+
+    #[test]
+    fn test_interleave() {
+        let nodes = two_nodes();
+        assert_eq!(interleave(&nodes, &[0, 1, 2, 4, 5, 6, 7]), vec![0, 4, 1, 5, 2, 6, 7]);
+        assert_eq!(interleave(&nodes, &[0, 9, 1]), vec![0, 1, 9]);
+        assert_eq!(interleave(&[], &[3, 1]), vec![3, 1]);
+    }
+
+    #[test]
+    fn test_placement() {
+        let nodes = two_nodes();
+        let cpus = interleave(&nodes, &[0, 1, 4, 5, 9]);
+        assert_eq!(cpus, vec![0, 4, 1, 5, 9]);
+        assert_eq!(placement(&nodes, &cpus, 3), vec![(Some(0), vec![0, 1]), (Some(1), vec![4])]);
+        assert_eq!(placement(&nodes, &cpus, 6), vec![(None, vec![9]), (Some(0), vec![0, 1, 0]), (Some(1), vec![4, 5])]);
+    }
+}
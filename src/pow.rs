@@ -6,26 +6,44 @@ use crate::{
     },
     proto::{RpcBlock, RpcBlockHeader},
     target::{self, Uint256},
-    Error,
+    Error, Hash,
 };
 use std::error::Error as StdError;
-use std::fmt;
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 mod hasher;
 mod mem_hash;
 
+/// What every hash of a template is computed from
 #[derive(Clone)]
-pub struct State {
-    #[allow(dead_code)]
-    pub id: usize,
-    pub nonce: u64,
+struct Work {
     target: Uint256,
-    block: RpcBlock,
     // PRE_POW_HASH || TIME || 32 zero byte padding; without NONCE
     hasher: PowHash,
     timestamp: u64,
 }
 
+/// The parts of a template that don't change while mining it, shared by all the mining threads
+struct Template {
+    id: usize,
+    block: RpcBlock,
+    // When the template was received, which is right before it replaces the previous one
+    received: Instant,
+}
+
+/// A mining thread's work on a template. Cloning it shares the block through the `Arc`,
+/// but copies the hashing state, so a clone made on a pinned mining thread lives on that thread's NUMA node.
+#[derive(Clone)]
+pub struct State {
+    template: Arc<Template>,
+    work: Work,
+    pub nonce: u64,
+}
+
 impl State {
     #[inline]
     pub fn new(id: usize, block: RpcBlock) -> Result<Self, Error> {
@@ -35,37 +53,40 @@ impl State {
         let mut hasher = HeaderHasher::new();
         serialize_header(&mut hasher, header, true);
         let pre_pow_hash = hasher.finalize();
-        let hasher = PowHash::new(pre_pow_hash, timestamp);
-
-        Ok(Self {
-            id,
-            nonce: 0,
-            target,
-            block,
-            hasher,
-            timestamp,
-        })
+        let work = Work { target, hasher: PowHash::new(pre_pow_hash, timestamp), timestamp };
+
+        Ok(Self { template: Arc::new(Template { id, block, received: Instant::now() }), work, nonce: 0 })
+    }
+
+    #[inline(always)]
+    pub fn id(&self) -> usize {
+        self.template.id
+    }
+
+    /// Time since the template was received
+    pub fn age(&self) -> Duration {
+        self.template.received.elapsed()
     }
 
     #[inline(always)]
     /// PRE_POW_HASH || TIME || 32 zero byte padding || NONCE
     pub fn calculate_pow(&self, nonce: u64) -> Uint256 {
         // Hasher already contains PRE_POW_HASH || TIME || 32 zero byte padding; only NONCE is missing
-        let block_hash = self.hasher.clone().finalize_with_nonce(nonce);
-        let hash = mem_hash(block_hash, self.timestamp, nonce);
+        let block_hash = self.work.hasher.clone().finalize_with_nonce(nonce);
+        let hash = mem_hash(block_hash, self.work.timestamp, nonce);
         Uint256::from_le_bytes(hash.as_bytes())
     }
 
     #[inline(always)]
     pub fn check_pow(&self, nonce: u64) -> bool {
         let pow = self.calculate_pow(nonce);
-        pow <= self.target
+        pow <= self.work.target
     }
 
     #[inline(always)]
     pub fn generate_block_if_pow(&mut self) -> Option<RpcBlock> {
         self.check_pow(self.nonce).then(|| {
-            let mut block = self.block.clone();
+            let mut block = self.template.block.clone();
             let header = block.header.as_mut().expect("Header exists on creation");
             header.nonce = self.nonce;
             block
@@ -73,6 +94,82 @@ impl State {
     }
 }
 
+/// Recomputes the PoW of a found block from its header alone, without any of the state cached for mining,
+/// and checks it against the target from `bits`. Returns the block hash.
+///
+/// The header is serialized and hashed separately from the mining path, but the memory-hard step
+/// is the same [`mem_hash`], so a bug in it isn't caught here. The fixed vector in the tests covers it.
+pub fn verify_block(block: &RpcBlock) -> Result<Hash, Error> {
+    let header = block.header.as_ref().ok_or("Header is missing")?;
+    let block_hash = block.block_hash().ok_or("Header is missing")?;
+    let pow = reference_pow(header)?;
+    let target = target::u256_from_compact_target(header.bits);
+    if pow > target {
+        return Err(format!(
+            "PoW of block {:x} with nonce {} is {:x}, above the target {:x}",
+            block_hash, header.nonce, pow, target
+        )
+        .into());
+    }
+    Ok(block_hash)
+}
+
+/// The header without its timestamp and nonce, written out byte by byte rather than with [`serialize_header`]
+fn reference_pre_pow_header(header: &RpcBlockHeader) -> Result<Vec<u8>, Error> {
+    fn hex(s: &str) -> Result<Vec<u8>, Error> {
+        // An odd number of digits is padded with a leading zero
+        let s = format!("{}{}", "0".repeat(s.len() % 2), s);
+        let bytes = (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16)).collect::<Result<_, _>>();
+        Ok(bytes?)
+    }
+    fn hash(s: &str) -> Result<Vec<u8>, Error> {
+        let hash = hex(s)?;
+        match hash.len() {
+            32 => Ok(hash),
+            len => Err(format!("Hash of {} bytes in the header: {}", len, s).into()),
+        }
+    }
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&u16::try_from(header.version)?.to_le_bytes());
+    bytes.extend_from_slice(&(header.parents.len() as u64).to_le_bytes());
+    for parent in &header.parents {
+        bytes.extend_from_slice(&(parent.parent_hashes.len() as u64).to_le_bytes());
+        for parent_hash in &parent.parent_hashes {
+            bytes.extend(hash(parent_hash)?);
+        }
+    }
+    bytes.extend(hash(&header.hash_merkle_root)?);
+    bytes.extend(hash(&header.accepted_id_merkle_root)?);
+    bytes.extend(hash(&header.utxo_commitment)?);
+    // Zero timestamp
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    bytes.extend_from_slice(&header.bits.to_le_bytes());
+    // Zero nonce
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    bytes.extend_from_slice(&header.daa_score.to_le_bytes());
+    bytes.extend_from_slice(&header.blue_score.to_le_bytes());
+    let blue_work = hex(&header.blue_work)?;
+    bytes.extend_from_slice(&(blue_work.len() as u64).to_le_bytes());
+    bytes.extend(blue_work);
+    bytes.extend(hash(&header.pruning_point)?);
+    Ok(bytes)
+}
+
+/// The PoW hashed in one go over PRE_POW_HASH || TIME || 32 zero byte padding || NONCE
+fn reference_pow(header: &RpcBlockHeader) -> Result<Uint256, Error> {
+    let mut key = [0u8; 32];
+    key[..9].copy_from_slice(b"BlockHash");
+    let pre_pow_hash = blake3::keyed_hash(&key, &reference_pre_pow_header(header)?);
+    let timestamp = header.timestamp as u64;
+    let mut input = [0u8; 80];
+    input[..32].copy_from_slice(pre_pow_hash.as_bytes());
+    input[32..40].copy_from_slice(&timestamp.to_le_bytes());
+    input[72..].copy_from_slice(&header.nonce.to_le_bytes());
+    let pow_hash = Uint256::from_le_bytes(*blake3::hash(&input).as_bytes());
+    Ok(Uint256::from_le_bytes(mem_hash(pow_hash, timestamp, header.nonce).as_bytes()))
+}
+
 #[cfg(not(any(target_pointer_width = "64", target_pointer_width = "32")))]
 compile_error!("Supporting only 32/64 bits");
 
@@ -168,4 +265,68 @@ fn decode_to_slice<T: AsRef<[u8]>>(data: T, out: &mut [u8]) -> Result<(), FromHe
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(all(test, not(feature = "shuttle")))]
+mod tests {
+    use super::*;
+    use crate::bench::synthetic_block;
+
+    #[test]
+    fn test_reference_pow_matches_mining() {
+        let state = State::new(0, synthetic_block(0x1e7fffff)).unwrap();
+        let mut block = synthetic_block(0x1e7fffff);
+        for nonce in [0, 1, 0xdead_beef, u64::MAX] {
+            block.header.as_mut().unwrap().nonce = nonce;
+            assert_eq!(reference_pow(block.header.as_ref().unwrap()).unwrap(), state.calculate_pow(nonce));
+        }
+    }
+
+    #[test]
+    fn test_pow_vector() {
+        let mut block = synthetic_block(0x1e7fffff);
+        block.header.as_mut().unwrap().nonce = 0xdead_beef;
+        let header = block.header.as_ref().unwrap();
+        // Field by field: version, no parents, the 3 roots, zero timestamp, bits, zero nonce,
+        // DAA score, blue score, blue work with its length, and the pruning point
+        let pre_pow_header = [
+            "0100",
+            "0000000000000000",
+            "23618af45051560529440541e7dc56be27676d278b1e00324b048d410a19d764",
+            "947d1a10378d6478b6957a0ed71866812dee33684968031b1cace4908c149d94",
+            "ec5e8fc0bc0c637004cee262cef12e7cf6d9cd7772513dbd466176a07ab7c4f4",
+            "0000000000000000",
+            "ffff7f1e",
+            "0000000000000000",
+            "78fc090000000000",
+            "83c4110000000000",
+            "0700000000000000",
+            "d8e28a03234786",
+            "be4c415d378f9113fabd3c09fcc84ddb6a00f900c87cb6a1186993ddc3014e2d",
+        ]
+        .concat();
+        assert_eq!(hex::encode(reference_pre_pow_header(header).unwrap()), pre_pow_header);
+        // Recorded from this implementation, so any change to the serialization or to `mem_hash` shows up
+        let pow = reference_pow(header).unwrap();
+        assert_eq!(format!("{:x}", pow), "b038650905ab0db157f6aef0153bb35e53c88f8fc8e0c5d12104c0a031751737");
+        assert_eq!(State::new(0, block.clone()).unwrap().calculate_pow(0xdead_beef), pow);
+    }
+
+    #[test]
+    fn test_verify_block() {
+        // A target of about 2^248, so a block is found every 256 hashes on average
+        let mut state = State::new(0, synthetic_block(0x2000ffff)).unwrap();
+        let block = loop {
+            if let Some(block) = state.generate_block_if_pow() {
+                break block;
+            }
+            state.nonce += 1;
+        };
+        assert_eq!(verify_block(&block).unwrap(), block.block_hash().unwrap());
+
+        let mut harder = block.clone();
+        harder.header.as_mut().unwrap().bits = 0x1d00ffff;
+        assert!(verify_block(&harder).is_err());
+        assert!(verify_block(&RpcBlock { header: None, ..block }).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{
+    governor::{Governor, LimitAction, LimitSource},
+    stats::MinerStats,
+    Error,
+};
+use log::{info, warn};
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+const POLL_RATE: Duration = Duration::from_secs(5);
+
+/// Where the machine takes its power from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerState {
+    pub on_battery: bool,
+    /// Charge of the emptiest battery in percent, `None` without a battery
+    pub capacity: Option<u8>,
+}
+
+impl fmt::Display for PowerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.on_battery, self.capacity) {
+            (true, Some(capacity)) => write!(f, "battery power at {}%", capacity),
+            (true, None) => write!(f, "battery power"),
+            (false, Some(capacity)) => write!(f, "AC power, battery at {}%", capacity),
+            (false, None) => write!(f, "AC power"),
+        }
+    }
+}
+
+/// Reads the power supplies under `sys_root/class/power_supply`, `None` when there are none.
+/// Batteries of devices like wireless mice and keyboards are ignored.
+pub fn read_power(sys_root: &Path) -> Result<Option<PowerState>, Error> {
+    let (mut external, mut online, mut discharging) = (false, false, false);
+    let mut capacity: Option<u8> = None;
+    let mut found = false;
+    let entries = match fs::read_dir(sys_root.join("class/power_supply")) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        let read = |file: &str| fs::read_to_string(path.join(file)).map(|value| value.trim().to_owned());
+        if read("scope").is_ok_and(|scope| scope == "Device") {
+            continue;
+        }
+        found = true;
+        if read("type").is_ok_and(|kind| kind == "Battery") {
+            if let Some(charge) = read("capacity").ok().and_then(|charge| charge.parse().ok()) {
+                capacity = Some(capacity.map_or(charge, |capacity| capacity.min(charge)));
+            }
+            discharging |= read("status").is_ok_and(|status| status == "Discharging");
+        } else if let Ok(supply_online) = read("online") {
+            external = true;
+            online |= supply_online == "1";
+        }
+    }
+    if !found {
+        return Ok(None);
+    }
+    // Without an AC adapter to ask, the battery tells if it's being drained
+    let on_battery = if external { !online } else { discharging };
+    Ok(Some(PowerState { on_battery, capacity }))
+}
+
+#[derive(Debug, Clone)]
+pub struct BatteryConfig {
+    /// What to do on battery, `None` keeps mining as long as the charge allows it
+    pub action: Option<LimitAction>,
+    /// Charge in percent below which mining on battery is paused
+    pub min_capacity: Option<u8>,
+    pub sys_root: PathBuf,
+}
+
+impl BatteryConfig {
+    /// The thread limit to apply while on this power
+    pub fn limit(&self, power: &PowerState) -> Option<usize> {
+        if !power.on_battery {
+            return None;
+        }
+        match (self.min_capacity, power.capacity) {
+            (Some(min_capacity), Some(capacity)) if capacity < min_capacity => Some(0),
+            _ => self.action.map(LimitAction::limit),
+        }
+    }
+}
+
+/// Polls the power supplies and limits mining through the governor while on battery
+pub async fn watch(config: BatteryConfig, governor: Arc<Governor>, stats: Arc<MinerStats>) {
+    let mut ticker = tokio::time::interval(POLL_RATE);
+    let mut last_power = None;
+    let mut last_limit = None;
+    loop {
+        ticker.tick().await;
+        let power = match read_power(&config.sys_root) {
+            Ok(Some(power)) => power,
+            Ok(None) => {
+                warn!("No power supplies in {}, not watching the battery", config.sys_root.display());
+                return;
+            }
+            Err(e) => {
+                warn!("Failed reading the power supplies from {}: {}", config.sys_root.display(), e);
+                continue;
+            }
+        };
+        if last_power.map(|last: PowerState| last.on_battery) != Some(power.on_battery) {
+            info!("Running on {}", power);
+        }
+        let limit = config.limit(&power);
+        if limit != last_limit {
+            stats.record_limit(LimitSource::Battery, last_limit, limit);
+            governor.set_limit(LimitSource::Battery, limit);
+        }
+        stats.set_power(power);
+        (last_power, last_limit) = (Some(power), limit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_supply(root: &Path, name: &str, files: &[(&str, &str)]) {
+        let dir = root.join("class/power_supply").join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, content) in files {
+            fs::write(dir.join(file), format!("{}\n", content)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_read_power() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir_all(root.path().join("class/power_supply")).unwrap();
+        assert_eq!(read_power(root.path()).unwrap(), None);
+
+        write_supply(root.path(), "BAT0", &[("type", "Battery"), ("capacity", "64"), ("status", "Charging")]);
+        write_supply(root.path(), "BAT1", &[("type", "Battery"), ("capacity", "80"), ("status", "Charging")]);
+        write_supply(root.path(), "AC", &[("type", "Mains"), ("online", "1")]);
+        let power = read_power(root.path()).unwrap().unwrap();
+        assert_eq!(power, PowerState { on_battery: false, capacity: Some(64) });
+        assert_eq!(power.to_string(), "AC power, battery at 64%");
+
+        write_supply(root.path(), "AC", &[("type", "Mains"), ("online", "0")]);
+        write_supply(root.path(), "usb", &[("type", "USB"), ("online", "0")]);
+        // A wireless mouse isn't what powers the machine
+        write_supply(
+            root.path(),
+            "hidpp_battery_0",
+            &[("type", "Battery"), ("scope", "Device"), ("capacity", "5"), ("status", "Discharging")],
+        );
+        assert_eq!(read_power(root.path()).unwrap(), Some(PowerState { on_battery: true, capacity: Some(64) }));
+
+        // Without an adapter the battery status decides
+        let root = TempDir::new().unwrap();
+        write_supply(root.path(), "BAT0", &[("type", "Battery"), ("capacity", "30"), ("status", "Discharging")]);
+        assert_eq!(read_power(root.path()).unwrap(), Some(PowerState { on_battery: true, capacity: Some(30) }));
+        let root = TempDir::new().unwrap();
+        write_supply(root.path(), "hid-mouse", &[("type", "Battery"), ("scope", "Device"), ("status", "Discharging")]);
+        assert_eq!(read_power(root.path()).unwrap(), None);
+        // Same as no power supplies at all
+        assert_eq!(read_power(TempDir::new().unwrap().path()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_battery_limit() {
+        let power = |on_battery, capacity| PowerState { on_battery, capacity: Some(capacity) };
+        let config = BatteryConfig { action: None, min_capacity: Some(20), sys_root: "/sys".into() };
+        assert_eq!(config.limit(&power(true, 50)), None);
+        assert_eq!(config.limit(&power(true, 19)), Some(0));
+        assert_eq!(config.limit(&power(false, 5)), None);
+
+        let config = BatteryConfig { action: Some("2".parse().unwrap()), ..config };
+        assert_eq!(config.limit(&power(true, 50)), Some(2));
+        assert_eq!(config.limit(&power(true, 10)), Some(0));
+        let config = BatteryConfig { action: Some("pause".parse().unwrap()), ..config };
+        assert_eq!(config.limit(&power(true, 50)), Some(0));
+    }
+}
@@ -0,0 +1,96 @@
+use std::{fmt, io, str::FromStr};
+
+/// Scheduling priority of the mining threads, the rest of the process keeps the default priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// `SCHED_IDLE`, only runs when nothing else wants the CPU
+    Idle,
+    /// A nice value, from -20 (highest priority) to 19 (lowest priority)
+    Nice(i32),
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("idle") {
+            return Ok(Self::Idle);
+        }
+        match s.parse() {
+            Ok(nice @ -20..=19) => Ok(Self::Nice(nice)),
+            _ => Err(format!("priority should be `idle` or a nice value between -20 and 19, got: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Priority::Idle => write!(f, "SCHED_IDLE"),
+            Priority::Nice(nice) => write!(f, "nice {}", nice),
+        }
+    }
+}
+
+/// Applies the priority to the calling thread only
+#[cfg(target_os = "linux")]
+pub fn set_current_thread_priority(priority: Priority) -> io::Result<()> {
+    // SAFETY: Both calls only take plain values, and `0`/`gettid()` target the calling thread.
+    let res = unsafe {
+        match priority {
+            Priority::Idle => {
+                let param = libc::sched_param { sched_priority: 0 };
+                libc::sched_setscheduler(0, libc::SCHED_IDLE, &param)
+            }
+            Priority::Nice(nice) => libc::setpriority(libc::PRIO_PROCESS as _, libc::gettid() as libc::id_t, nice),
+        }
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_current_thread_priority(_priority: Priority) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Thread priorities are only supported on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_priority() {
+        assert_eq!("idle".parse(), Ok(Priority::Idle));
+        assert_eq!("IDLE".parse(), Ok(Priority::Idle));
+        assert_eq!("19".parse(), Ok(Priority::Nice(19)));
+        assert_eq!("-5".parse(), Ok(Priority::Nice(-5)));
+        assert!("20".parse::<Priority>().is_err());
+        assert!("-21".parse::<Priority>().is_err());
+        assert!("low".parse::<Priority>().is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_set_current_thread_priority() {
+        fn current_nice() -> i32 {
+            // SAFETY: getpriority only reads the nice value of the calling thread
+            unsafe { libc::getpriority(libc::PRIO_PROCESS as _, libc::gettid() as libc::id_t) }
+        }
+        let main_nice = current_nice();
+        let (nice, policy) = std::thread::spawn(|| {
+            set_current_thread_priority(Priority::Nice(19)).unwrap();
+            let nice = current_nice();
+            set_current_thread_priority(Priority::Idle).unwrap();
+            // SAFETY: Reads the scheduling policy of the calling thread
+            (nice, unsafe { libc::sched_getscheduler(0) })
+        })
+        .join()
+        .unwrap();
+        assert_eq!(nice, 19);
+        assert_eq!(policy, libc::SCHED_IDLE);
+        // Other threads are left alone
+        assert_eq!(current_nice(), main_nice);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{
+    governor::{Governor, LimitAction, LimitSource},
+    stats::MinerStats,
+    Error,
+};
+use log::{info, warn};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+const POLL_RATE: Duration = Duration::from_secs(5);
+// The kernel truncates process names in `comm` to this many bytes
+const COMM_LEN: usize = 15;
+
+/// A process name, where `*` matches any run of characters and `?` a single one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessPattern(String);
+
+impl FromStr for ProcessPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "" => Err("process pattern can't be empty".to_owned()),
+            pattern => Ok(Self(pattern.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for ProcessPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, _) => name.is_empty(),
+        (Some(('*', rest)), _) => glob_match(rest, name) || (!name.is_empty() && glob_match(pattern, &name[1..])),
+        (Some(('?', rest)), Some((_, name_rest))) => glob_match(rest, name_rest),
+        (Some((c, rest)), Some((n, name_rest))) => c == n && glob_match(rest, name_rest),
+        (Some(_), None) => false,
+    }
+}
+
+impl ProcessPattern {
+    pub fn matches(&self, comm: &str) -> bool {
+        let pattern: Vec<char> = self.0.chars().collect();
+        let name: Vec<char> = comm.chars().collect();
+        if glob_match(&pattern, &name) {
+            return true;
+        }
+        // A longer name only shows up by its first bytes
+        !self.0.contains(['*', '?']) && self.0.len() > COMM_LEN && comm.len() == COMM_LEN && self.0.starts_with(comm)
+    }
+}
+
+/// The names of the running processes that match any of the patterns, sorted and deduplicated.
+/// Processes that exit while scanning are skipped.
+pub fn running_matches(proc_root: &Path, patterns: &[ProcessPattern]) -> Result<Vec<String>, Error> {
+    let mut matches = Vec::new();
+    for entry in fs::read_dir(proc_root)? {
+        let entry = entry?;
+        if !entry.file_name().to_str().is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit())) {
+            continue;
+        }
+        let Ok(comm) = fs::read_to_string(entry.path().join("comm")) else {
+            continue;
+        };
+        let comm = comm.trim_end_matches('\n');
+        if patterns.iter().any(|pattern| pattern.matches(comm)) {
+            matches.push(comm.to_owned());
+        }
+    }
+    matches.sort();
+    matches.dedup();
+    Ok(matches)
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcessConfig {
+    pub patterns: Vec<ProcessPattern>,
+    /// What to do while any of them runs
+    pub action: LimitAction,
+    pub proc_root: PathBuf,
+}
+
+/// Polls the running processes and limits mining through the governor while a listed one runs.
+/// Only the threads are parked, so mining resumes on the current template as soon as they exit.
+pub async fn watch(config: ProcessConfig, governor: Arc<Governor>, stats: Arc<MinerStats>) {
+    let mut ticker = tokio::time::interval(POLL_RATE);
+    let mut last_matches = Vec::new();
+    loop {
+        ticker.tick().await;
+        let matches = match running_matches(&config.proc_root, &config.patterns) {
+            Ok(matches) => matches,
+            Err(e) => {
+                warn!("Failed scanning the processes in {}: {}", config.proc_root.display(), e);
+                continue;
+            }
+        };
+        if matches == last_matches {
+            continue;
+        }
+        match (matches.is_empty(), last_matches.is_empty()) {
+            (false, true) => {
+                info!("Listed processes are running: {}", matches.join(", "));
+                let limit = Some(config.action.limit());
+                stats.record_limit(LimitSource::Processes, None, limit);
+                governor.set_limit(LimitSource::Processes, limit);
+            }
+            (true, false) => {
+                info!("Listed processes exited");
+                stats.record_limit(LimitSource::Processes, Some(config.action.limit()), None);
+                governor.set_limit(LimitSource::Processes, None);
+            }
+            _ => info!("Listed processes are running: {}", matches.join(", ")),
+        }
+        last_matches = matches;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn patterns(patterns: &[&str]) -> Vec<ProcessPattern> {
+        patterns.iter().map(|pattern| pattern.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_pattern_matches() {
+        let [rustc, cc, zoom, steam, long] = ["rustc", "cc1*", "zoom?", "*steam*", "gnome-shell-calendar-server"]
+            .map(|pattern| pattern.parse::<ProcessPattern>().unwrap());
+        assert!(rustc.matches("rustc") && !rustc.matches("rustc-wrapper"));
+        assert!(cc.matches("cc1plus") && cc.matches("cc1") && !cc.matches("xcc1"));
+        assert!(zoom.matches("zoom1") && !zoom.matches("zoom"));
+        assert!(steam.matches("steamwebhelper") && steam.matches("steam"));
+        // `comm` only keeps the first 15 bytes of the name
+        assert!(long.matches("gnome-shell-cal") && !long.matches("gnome-shell"));
+        assert!(" ".parse::<ProcessPattern>().is_err());
+    }
+
+    #[test]
+    fn test_running_matches() {
+        let root = TempDir::new().unwrap();
+        for (dir, comm) in [("1", "systemd"), ("42", "cc1plus"), ("43", "cc1plus"), ("100", "zoom"), ("self", "rustc")]
+        {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+            fs::write(root.path().join(dir).join("comm"), format!("{}\n", comm)).unwrap();
+        }
+        // A process that exited while scanning
+        fs::create_dir_all(root.path().join("77")).unwrap();
+        fs::write(root.path().join("stat"), "cpu 1 2 3 4\n").unwrap();
+
+        assert_eq!(running_matches(root.path(), &patterns(&["cc1*", "zoom", "rustc"])).unwrap(), ["cc1plus", "zoom"]);
+        assert!(running_matches(root.path(), &patterns(&["steam"])).unwrap().is_empty());
+        assert!(running_matches(&root.path().join("missing"), &patterns(&["zoom"])).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use crate::governor::{Governor, LimitSource};
+use chrono::{Datelike, Local, NaiveDateTime, Timelike, Weekday};
+use std::{fmt, str::FromStr, sync::Arc, time::Duration};
+
+const POLL_RATE: Duration = Duration::from_secs(10);
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// Days of the week as a bitmask, Monday is the lowest bit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Days(u8);
+
+impl Days {
+    const ALL: Days = Days(0b111_1111);
+    const WEEKDAYS: Days = Days(0b001_1111);
+    const WEEKENDS: Days = Days(0b110_0000);
+
+    fn contains(self, day: Weekday) -> bool {
+        self.0 & (1 << day.num_days_from_monday()) != 0
+    }
+
+    fn parse_day(s: &str) -> Option<Weekday> {
+        // chrono accepts both short and full names
+        s.parse().ok()
+    }
+}
+
+impl FromStr for Days {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "daily" | "everyday" => return Ok(Days::ALL),
+            "weekdays" => return Ok(Days::WEEKDAYS),
+            "weekends" => return Ok(Days::WEEKENDS),
+            _ => {}
+        }
+        let err = || format!("Invalid days `{}`, expected e.g. weekdays, weekends, daily, mon or mon-fri", s);
+        let (first, last) = match s.split_once('-') {
+            Some((first, last)) => (Self::parse_day(first).ok_or_else(err)?, Self::parse_day(last).ok_or_else(err)?),
+            None => {
+                let day = Self::parse_day(s).ok_or_else(err)?;
+                (day, day)
+            }
+        };
+        // Ranges can wrap around the week, like fri-mon
+        let mut days = 0;
+        let mut day = first;
+        loop {
+            days |= 1 << day.num_days_from_monday();
+            if day == last {
+                return Ok(Days(days));
+            }
+            day = day.succ();
+        }
+    }
+}
+
+/// Minutes since midnight, a start after the end wraps past midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TimeRange {
+    start: u32,
+    end: u32,
+}
+
+impl TimeRange {
+    const ALL_DAY: TimeRange = TimeRange { start: 0, end: MINUTES_PER_DAY };
+
+    fn parse_time(s: &str) -> Option<u32> {
+        let (hours, minutes) = s.split_once(':')?;
+        let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+        let time = hours * 60 + minutes;
+        (minutes < 60 && time <= MINUTES_PER_DAY).then_some(time)
+    }
+}
+
+impl FromStr for TimeRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid time range `{}`, expected e.g. 19:00-07:00", s);
+        let (start, end) = s.split_once('-').ok_or_else(err)?;
+        let (start, end) = (Self::parse_time(start).ok_or_else(err)?, Self::parse_time(end).ok_or_else(err)?);
+        if start == end || start == MINUTES_PER_DAY {
+            return Err(err());
+        }
+        Ok(TimeRange { start, end })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Window {
+    days: Days,
+    time: TimeRange,
+}
+
+impl Window {
+    fn contains(&self, time: NaiveDateTime) -> bool {
+        let minute = time.hour() * 60 + time.minute();
+        let day = time.weekday();
+        let TimeRange { start, end } = self.time;
+        if start < end {
+            self.days.contains(day) && start <= minute && minute < end
+        } else {
+            // The part after midnight belongs to the day the window started
+            (self.days.contains(day) && minute >= start) || (self.days.contains(day.pred()) && minute < end)
+        }
+    }
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let (days, time) = match words.as_slice() {
+            [] => return Err("Empty mining window".into()),
+            [days @ .., all, day] if all.eq_ignore_ascii_case("all") && day.eq_ignore_ascii_case("day") => {
+                (days, TimeRange::ALL_DAY)
+            }
+            [days @ .., time] => (days, time.parse()?),
+        };
+        let days = match days {
+            [] => Days::ALL,
+            [every, day] if every.eq_ignore_ascii_case("every") && day.eq_ignore_ascii_case("day") => Days::ALL,
+            days => days
+                .iter()
+                .map(|days| days.parse::<Days>())
+                .try_fold(Days(0), |all, days| Ok::<_, String>(Days(all.0 | days?.0)))?,
+        };
+        Ok(Window { days, time })
+    }
+}
+
+/// When mining is allowed, as a comma separated list of windows like `weekdays 19:00-07:00, weekends all day`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    windows: Vec<Window>,
+    text: String,
+}
+
+impl Schedule {
+    pub fn is_active(&self, time: NaiveDateTime) -> bool {
+        self.windows.iter().any(|window| window.contains(time))
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let windows = s.split([',', ';']).map(str::parse).collect::<Result<Vec<Window>, _>>()?;
+        Ok(Schedule { windows, text: s.trim().to_owned() })
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Pauses mining through the governor outside of the schedule, in local time
+pub async fn watch(schedule: Schedule, governor: Arc<Governor>) {
+    let mut ticker = tokio::time::interval(POLL_RATE);
+    loop {
+        ticker.tick().await;
+        let active = schedule.is_active(Local::now().naive_local());
+        governor.set_limit(LimitSource::Schedule, (!active).then_some(0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    // 2024-01-01 is a Monday
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_schedule() {
+        let schedule: Schedule = "weekdays 19:00-07:00, weekends all day".parse().unwrap();
+        assert_eq!(
+            schedule.windows,
+            vec![
+                Window { days: Days::WEEKDAYS, time: TimeRange { start: 19 * 60, end: 7 * 60 } },
+                Window { days: Days::WEEKENDS, time: TimeRange::ALL_DAY },
+            ]
+        );
+        assert_eq!(schedule.to_string(), "weekdays 19:00-07:00, weekends all day");
+
+        assert_eq!("fri-mon".parse::<Days>(), Ok(Days(0b111_0001)));
+        assert_eq!("Wednesday".parse::<Days>(), Ok(Days(0b000_0100)));
+        let window: Window = "sat sun 08:00-24:00".parse().unwrap();
+        assert_eq!(window, Window { days: Days::WEEKENDS, time: TimeRange { start: 8 * 60, end: 24 * 60 } });
+        assert_eq!("01:30-02:00".parse::<Window>().unwrap().days, Days::ALL);
+        assert_eq!("every day all day".parse::<Window>().unwrap().time, TimeRange::ALL_DAY);
+
+        for invalid in ["", "weekdays", "someday 10:00-11:00", "10:00-10:00", "25:00-01:00", "10:60-11:00", "a, "] {
+            assert!(invalid.parse::<Schedule>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_schedule_is_active() {
+        let schedule: Schedule = "weekdays 19:00-07:00, weekends all day".parse().unwrap();
+        // Monday
+        assert!(!schedule.is_active(at(1, 6, 59)));
+        assert!(!schedule.is_active(at(1, 12, 0)));
+        assert!(schedule.is_active(at(1, 19, 0)));
+        assert!(schedule.is_active(at(2, 6, 59)));
+        assert!(!schedule.is_active(at(2, 7, 0)));
+        // Friday night into the weekend
+        assert!(schedule.is_active(at(5, 23, 0)));
+        assert!(schedule.is_active(at(6, 12, 0)));
+        assert!(schedule.is_active(at(7, 23, 59)));
+        // The weekday window doesn't start on Sunday evening, so Monday morning isn't covered
+        assert!(!schedule.is_active(at(8, 3, 0)));
+    }
+}
@@ -0,0 +1,78 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+/// Whether to submit a block found on a template that was already replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StalePolicy {
+    /// Always submit, the node may still merge it
+    #[default]
+    Submit,
+    /// Never submit
+    Drop,
+    /// Submit only if the template was replaced at most this long ago
+    MaxAge(Duration),
+}
+
+impl StalePolicy {
+    /// `stale_for` is the time since the block's template was replaced, `None` for a block on the current template
+    pub fn should_submit(&self, stale_for: Option<Duration>) -> bool {
+        match (self, stale_for) {
+            (_, None) | (Self::Submit, _) => true,
+            (Self::Drop, Some(_)) => false,
+            (Self::MaxAge(max_age), Some(stale_for)) => stale_for <= *max_age,
+        }
+    }
+}
+
+impl FromStr for StalePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("stale-policy should be `submit`, `drop` or a maximum age like 500ms or 2s, got: {}", s);
+        match s.to_ascii_lowercase().as_str() {
+            "submit" => Ok(Self::Submit),
+            "drop" => Ok(Self::Drop),
+            age => {
+                let (number, unit) = match age.strip_suffix("ms") {
+                    Some(millis) => (millis, 1e-3),
+                    None => (age.strip_suffix('s').unwrap_or(age), 1.0),
+                };
+                let secs = number.trim().parse::<f64>().map_err(|_| err())? * unit;
+                Duration::try_from_secs_f64(secs).map(Self::MaxAge).map_err(|_| err())
+            }
+        }
+    }
+}
+
+impl fmt::Display for StalePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Submit => write!(f, "submit"),
+            Self::Drop => write!(f, "drop"),
+            Self::MaxAge(max_age) => write!(f, "submit if replaced at most {:?} ago", max_age),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_policy() {
+        assert_eq!("submit".parse(), Ok(StalePolicy::Submit));
+        assert_eq!("Drop".parse(), Ok(StalePolicy::Drop));
+        assert_eq!("500ms".parse(), Ok(StalePolicy::MaxAge(Duration::from_millis(500))));
+        assert_eq!("2s".parse(), Ok(StalePolicy::MaxAge(Duration::from_secs(2))));
+        assert_eq!("1.5".parse(), Ok(StalePolicy::MaxAge(Duration::from_millis(1500))));
+        assert!("-1s".parse::<StalePolicy>().is_err());
+        assert!("later".parse::<StalePolicy>().is_err());
+
+        let secs = |secs| Some(Duration::from_secs(secs));
+        assert!(StalePolicy::Drop.should_submit(None));
+        assert!(!StalePolicy::Drop.should_submit(secs(0)));
+        assert!(StalePolicy::Submit.should_submit(secs(100)));
+        let max_age = StalePolicy::MaxAge(Duration::from_secs(2));
+        assert!(max_age.should_submit(secs(2)));
+        assert!(!max_age.should_submit(secs(3)));
+    }
+}
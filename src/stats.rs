@@ -0,0 +1,356 @@
+use crate::{governor::LimitSource, power::PowerState, thermal::Temperature};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// A thread mining slower than this fraction of the median thread is reported as slow
+const SLOW_THREAD_RATIO: f64 = 0.75;
+
+/// Counters of a single mining thread.
+/// Aligned to 128 bytes (two cache lines, to also defeat adjacent line prefetching),
+/// so threads never write to a cache line shared with another thread.
+#[repr(align(128))]
+#[derive(Debug)]
+pub struct ThreadStats {
+    pub id: usize,
+    hashes: AtomicU64,
+    // Whether the thread was parked since the counters were last taken
+    parked: AtomicBool,
+}
+
+impl ThreadStats {
+    #[inline(always)]
+    pub fn add_hashes(&self, hashes: u64) {
+        self.hashes.fetch_add(hashes, Ordering::Relaxed);
+    }
+
+    /// Called periodically while the thread is parked, so it isn't reported as a slow thread
+    #[inline]
+    pub fn mark_parked(&self) {
+        self.parked.store(true, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn take_hashes(&self) -> u64 {
+        self.hashes.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MinerStats {
+    threads: Mutex<Vec<Arc<ThreadStats>>>,
+    next_thread_id: AtomicUsize,
+    // Hashes of removed or parked threads that weren't taken yet
+    retired_hashes: AtomicU64,
+    blocks_found: AtomicU64,
+    thread_restarts: AtomicU64,
+    // Found blocks that failed re-verification
+    hardware_errors: AtomicU64,
+    // Blocks found on templates that were already replaced
+    stale_submitted: AtomicU64,
+    stale_dropped: AtomicU64,
+    // Time threads kept mining a template after it was replaced, in nanoseconds
+    stale_switches: AtomicU64,
+    stale_nanos: AtomicU64,
+    max_stale_nanos: AtomicU64,
+    // Latest reading of the thermal zones, empty when not watched
+    temperatures: Mutex<Vec<Temperature>>,
+    // Latest reading of the power supplies, `None` when not watched
+    power: Mutex<Option<PowerState>>,
+    // How often every source paused, limited and resumed mining
+    pause_transitions: Mutex<BTreeMap<LimitSource, PauseTransitions>>,
+}
+
+/// How often a source paused or limited mining, and let it run unlimited again
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PauseTransitions {
+    pub pauses: u64,
+    pub limits: u64,
+    pub resumes: u64,
+}
+
+impl MinerStats {
+    /// Creates the counters for a new mining thread
+    pub fn register_thread(&self) -> Arc<ThreadStats> {
+        let id = self.next_thread_id.fetch_add(1, Ordering::Relaxed);
+        let stats = Arc::new(ThreadStats { id, hashes: AtomicU64::new(0), parked: AtomicBool::new(false) });
+        self.threads.lock().unwrap_or_else(|e| e.into_inner()).push(Arc::clone(&stats));
+        stats
+    }
+
+    /// Removes the counters of a thread that exited, keeping its remaining hashes for the next [`Self::take_retired_hashes`]
+    pub fn unregister_thread(&self, stats: &Arc<ThreadStats>) {
+        self.threads.lock().unwrap_or_else(|e| e.into_inner()).retain(|thread| !Arc::ptr_eq(thread, stats));
+        self.retired_hashes.fetch_add(stats.take_hashes(), Ordering::Relaxed);
+    }
+
+    pub fn take_retired_hashes(&self) -> u64 {
+        self.retired_hashes.swap(0, Ordering::Relaxed)
+    }
+
+    /// Returns the hashes every thread tried since the last call, by thread id.
+    /// Threads that were parked in the meantime are left out, and their hashes go to [`Self::take_retired_hashes`].
+    pub fn take_thread_hashes(&self) -> Vec<(usize, u64)> {
+        let threads = self.threads.lock().unwrap_or_else(|e| e.into_inner());
+        let mut thread_hashes = Vec::with_capacity(threads.len());
+        for thread in threads.iter() {
+            let hashes = thread.take_hashes();
+            if thread.parked.swap(false, Ordering::Relaxed) {
+                self.retired_hashes.fetch_add(hashes, Ordering::Relaxed);
+            } else {
+                thread_hashes.push((thread.id, hashes));
+            }
+        }
+        thread_hashes
+    }
+
+    #[inline]
+    pub fn add_block_found(&self) {
+        self.blocks_found.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_temperatures(&self, temperatures: Vec<Temperature>) {
+        *self.temperatures.lock().unwrap_or_else(|e| e.into_inner()) = temperatures;
+    }
+
+    pub fn temperatures(&self) -> Vec<Temperature> {
+        self.temperatures.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn set_power(&self, power: PowerState) {
+        *self.power.lock().unwrap_or_else(|e| e.into_inner()) = Some(power);
+    }
+
+    pub fn power(&self) -> Option<PowerState> {
+        *self.power.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Counts a source changing its thread limit, where 0 pauses mining and `None` lifts the limit
+    pub fn record_limit(&self, source: LimitSource, previous: Option<usize>, limit: Option<usize>) {
+        if previous == limit {
+            return;
+        }
+        let mut transitions = self.pause_transitions.lock().unwrap_or_else(|e| e.into_inner());
+        let transitions = transitions.entry(source).or_default();
+        match limit {
+            Some(0) => transitions.pauses += 1,
+            Some(_) => transitions.limits += 1,
+            None => transitions.resumes += 1,
+        }
+    }
+
+    /// The pauses and resumes so far, by source
+    pub fn pause_transitions(&self) -> Vec<(LimitSource, PauseTransitions)> {
+        let transitions = self.pause_transitions.lock().unwrap_or_else(|e| e.into_inner());
+        transitions.iter().map(|(&source, &transitions)| (source, transitions)).collect()
+    }
+
+    #[inline]
+    pub fn blocks_found(&self) -> u64 {
+        self.blocks_found.load(Ordering::Relaxed)
+    }
+
+    /// Records how long a thread kept mining a template after it was replaced
+    pub fn add_stale_work(&self, stale: Duration) {
+        let nanos = stale.as_nanos().try_into().unwrap_or(u64::MAX);
+        self.stale_switches.fetch_add(1, Ordering::Relaxed);
+        self.stale_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_stale_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Returns the stale work since the last call
+    pub fn take_stale_work(&self) -> StaleWork {
+        StaleWork {
+            switches: self.stale_switches.swap(0, Ordering::Relaxed),
+            total: Duration::from_nanos(self.stale_nanos.swap(0, Ordering::Relaxed)),
+            max: Duration::from_nanos(self.max_stale_nanos.swap(0, Ordering::Relaxed)),
+        }
+    }
+
+    pub fn add_stale_solution(&self, submitted: bool) {
+        match submitted {
+            true => self.stale_submitted.fetch_add(1, Ordering::Relaxed),
+            false => self.stale_dropped.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// Blocks found on replaced templates so far, as (submitted, dropped)
+    pub fn stale_solutions(&self) -> (u64, u64) {
+        (self.stale_submitted.load(Ordering::Relaxed), self.stale_dropped.load(Ordering::Relaxed))
+    }
+
+    pub fn add_hardware_error(&self) {
+        self.hardware_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn hardware_errors(&self) -> u64 {
+        self.hardware_errors.load(Ordering::Relaxed)
+    }
+
+    pub fn add_thread_restart(&self) {
+        self.thread_restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many times a mining thread died and had to be restarted
+    pub fn thread_restarts(&self) -> u64 {
+        self.thread_restarts.load(Ordering::Relaxed)
+    }
+}
+
+/// How long threads kept mining templates that were already replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StaleWork {
+    /// Template switches that interrupted a thread mid-work
+    pub switches: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl StaleWork {
+    pub fn average(&self) -> Option<Duration> {
+        (self.switches != 0).then(|| self.total / self.switches.try_into().unwrap_or(u32::MAX))
+    }
+}
+
+/// Hashrate of every thread over a single logging period
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadRates(pub Vec<(usize, f64)>);
+
+impl ThreadRates {
+    pub fn new(thread_hashes: &[(usize, u64)], secs: f64) -> Self {
+        Self(thread_hashes.iter().map(|&(id, hashes)| (id, hashes as f64 / secs)).collect())
+    }
+
+    pub fn median(&self) -> Option<f64> {
+        let mut rates: Vec<f64> = self.0.iter().map(|&(_, rate)| rate).collect();
+        rates.sort_by(f64::total_cmp);
+        let mid = rates.len() / 2;
+        match rates.len() {
+            0 => None,
+            len if len % 2 == 0 => Some((rates[mid - 1] + rates[mid]) / 2.0),
+            _ => Some(rates[mid]),
+        }
+    }
+
+    /// Threads that are much slower than the median thread, with their rate relative to the median
+    pub fn slow_threads(&self) -> Vec<(usize, f64)> {
+        match self.median() {
+            Some(median) if median > 0.0 => self
+                .0
+                .iter()
+                .map(|&(id, rate)| (id, rate / median))
+                .filter(|&(_, ratio)| ratio < SLOW_THREAD_RATIO)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_stats_are_padded() {
+        assert!(std::mem::align_of::<ThreadStats>() >= 128);
+        let stats = MinerStats::default();
+        let a = stats.register_thread();
+        let b = stats.register_thread();
+        let distance = (Arc::as_ptr(&a) as usize).abs_diff(Arc::as_ptr(&b) as usize);
+        assert!(distance >= 128);
+        assert_eq!((a.id, b.id), (0, 1));
+    }
+
+    #[test]
+    fn test_take_thread_hashes() {
+        let stats = MinerStats::default();
+        let a = stats.register_thread();
+        let b = stats.register_thread();
+        a.add_hashes(128);
+        a.add_hashes(128);
+        b.add_hashes(128);
+        assert_eq!(stats.take_thread_hashes(), vec![(0, 256), (1, 128)]);
+        assert_eq!(stats.take_thread_hashes(), vec![(0, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn test_unregister_thread() {
+        let stats = MinerStats::default();
+        let a = stats.register_thread();
+        let b = stats.register_thread();
+        a.add_hashes(128);
+        b.add_hashes(256);
+        stats.unregister_thread(&a);
+        assert_eq!(stats.take_thread_hashes(), vec![(1, 256)]);
+        assert_eq!(stats.take_retired_hashes(), 128);
+        assert_eq!(stats.take_retired_hashes(), 0);
+        // Ids are never reused
+        assert_eq!(stats.register_thread().id, 2);
+    }
+
+    #[test]
+    fn test_parked_threads() {
+        let stats = MinerStats::default();
+        let a = stats.register_thread();
+        let b = stats.register_thread();
+        a.add_hashes(128);
+        b.add_hashes(256);
+        b.mark_parked();
+        assert_eq!(stats.take_thread_hashes(), vec![(0, 128)]);
+        assert_eq!(stats.take_retired_hashes(), 256);
+        assert_eq!(stats.take_thread_hashes(), vec![(0, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn test_stale_work() {
+        let stats = MinerStats::default();
+        assert_eq!(stats.take_stale_work().average(), None);
+        stats.add_stale_work(Duration::from_micros(10));
+        stats.add_stale_work(Duration::from_micros(30));
+        let stale = stats.take_stale_work();
+        assert_eq!(stale, StaleWork { switches: 2, total: Duration::from_micros(40), max: Duration::from_micros(30) });
+        assert_eq!(stale.average(), Some(Duration::from_micros(20)));
+        assert_eq!(stats.take_stale_work(), StaleWork::default());
+
+        stats.add_stale_solution(true);
+        stats.add_stale_solution(false);
+        stats.add_stale_solution(false);
+        assert_eq!(stats.stale_solutions(), (1, 2));
+    }
+
+    #[test]
+    fn test_pause_transitions() {
+        let stats = MinerStats::default();
+        stats.record_limit(LimitSource::Battery, None, Some(0));
+        stats.record_limit(LimitSource::Battery, Some(0), None);
+        stats.record_limit(LimitSource::Battery, None, Some(0));
+        stats.record_limit(LimitSource::Processes, None, Some(2));
+        stats.record_limit(LimitSource::Processes, Some(2), Some(2));
+        assert_eq!(
+            stats.pause_transitions(),
+            vec![
+                (LimitSource::Battery, PauseTransitions { pauses: 2, limits: 0, resumes: 1 }),
+                (LimitSource::Processes, PauseTransitions { pauses: 0, limits: 1, resumes: 0 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thread_rates() {
+        let rates = ThreadRates::new(&[(0, 1000), (1, 1100), (2, 300), (3, 900), (4, 1000)], 10.0);
+        assert_eq!(rates.median(), Some(100.0));
+        assert_eq!(rates.slow_threads(), vec![(2, 0.3)]);
+
+        let rates = ThreadRates::new(&[(0, 10), (1, 20)], 1.0);
+        assert_eq!(rates.median(), Some(15.0));
+        assert_eq!(rates.slow_threads(), vec![(0, 10.0 / 15.0)]);
+
+        assert_eq!(ThreadRates::new(&[], 1.0).median(), None);
+        assert!(ThreadRates::new(&[(0, 0), (1, 0)], 1.0).slow_threads().is_empty());
+    }
+}
@@ -1,22 +1,52 @@
 use arc_swap::{ArcSwapOption, Guard, RefCnt};
-use std::ops::Deref;
+use std::{fmt, ops::Deref, time::Duration};
 
-use sync::{Arc, Condvar, Mutex};
+use sync::{Arc, AtomicBool, AtomicUsize, Condvar, Mutex, Ordering};
 
 pub struct Shared<T> {
     inner: ArcSwapOption<T>,
+    // Bumped after every swap, so receivers can cheaply poll for a change
+    generation: AtomicUsize,
+    closed: AtomicBool,
     wait_cv: Condvar,
     wait_mutex: Mutex<()>,
 }
 
+/// Returned by the waiting functions once [`WatchSwap::close`] was called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Closed;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitTimeoutError {
+    Timeout,
+    Closed,
+}
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel is closed")
+    }
+}
+
+impl fmt::Display for WaitTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitTimeoutError::Timeout => write!(f, "timed out waiting for a change"),
+            WaitTimeoutError::Closed => Closed.fmt(f),
+        }
+    }
+}
+
 pub struct WatchSwap<T> {
     shared: Arc<Shared<T>>,
     cached: Option<Arc<T>>,
+    // The generation when `cached` was last compared
+    seen_generation: usize,
 }
 
 impl<T> Clone for WatchSwap<T> {
     fn clone(&self) -> Self {
-        Self { shared: Arc::clone(&self.shared), cached: self.cached.clone() }
+        Self { shared: Arc::clone(&self.shared), cached: self.cached.clone(), seen_generation: self.seen_generation }
     }
 }
 
@@ -27,10 +57,13 @@ impl<T> WatchSwap<T> {
         Self {
             shared: Arc::new(Shared {
                 inner: ArcSwapOption::new(val.clone()),
+                generation: AtomicUsize::new(0),
+                closed: AtomicBool::new(false),
                 wait_cv: Condvar::new(),
                 wait_mutex: Mutex::new(()),
             }),
             cached: val,
+            seen_generation: 0,
         }
     }
 
@@ -38,10 +71,13 @@ impl<T> WatchSwap<T> {
         Self {
             shared: Arc::new(Shared {
                 inner: ArcSwapOption::const_empty(),
+                generation: AtomicUsize::new(0),
+                closed: AtomicBool::new(false),
                 wait_cv: Condvar::new(),
                 wait_mutex: Mutex::new(()),
             }),
             cached: None,
+            seen_generation: 0,
         }
     }
 
@@ -52,10 +88,16 @@ impl<T> WatchSwap<T> {
     }
 
     #[inline]
-    fn get_changed_inner<'a>(cached: &'a mut Option<Arc<T>>, inner: &'a ArcSwapOption<T>) -> bool {
+    fn get_changed_inner<'a>(
+        cached: &'a mut Option<Arc<T>>,
+        seen_generation: &mut usize,
+        shared: &'a Shared<T>,
+    ) -> bool {
+        // Read before the value, so a swap racing with this load still shows up as a new generation
+        *seen_generation = shared.generation.load(Ordering::Acquire);
         // TODO: Optimize using `arc_swap::Cache` when https://github.com/vorner/arc-swap/pull/91 is merged.
         let cur_ptr = RefCnt::as_ptr(&*cached);
-        let cheap_load = inner.load();
+        let cheap_load = shared.inner.load();
         if cur_ptr != RefCnt::as_ptr(&*cheap_load) {
             *cached = Guard::into_inner(cheap_load);
             true
@@ -66,7 +108,25 @@ impl<T> WatchSwap<T> {
 
     #[inline]
     pub fn get_changed(&mut self) -> Option<impl Deref<Target = Option<Arc<T>>> + '_> {
-        Self::get_changed_inner(&mut self.cached, &self.shared.inner).then_some(&self.cached)
+        Self::get_changed_inner(&mut self.cached, &mut self.seen_generation, &self.shared).then_some(&self.cached)
+    }
+
+    /// A single atomic load telling if the value might have changed since it was last read,
+    /// cheap enough to poll between every unit of work. Confirm with [`Self::get_changed`].
+    #[inline(always)]
+    pub fn has_changed(&self) -> bool {
+        self.shared.generation.load(Ordering::Acquire) != self.seen_generation
+    }
+
+    /// Picks up the latest value, whether it changed since it was last read or not
+    pub fn get_latest(&mut self) -> impl Deref<Target = Option<Arc<T>>> + '_ {
+        Self::get_changed_inner(&mut self.cached, &mut self.seen_generation, &self.shared);
+        &self.cached
+    }
+
+    /// The latest value, even if this receiver didn't pick it up yet
+    pub fn latest(&self) -> Option<Arc<T>> {
+        self.shared.inner.load_full()
     }
 
     #[allow(dead_code)]
@@ -76,27 +136,73 @@ impl<T> WatchSwap<T> {
 
     pub fn swap(&self, val: impl Into<Option<T>>) -> Option<Arc<T>> {
         let old = self.shared.inner.swap(val.into().map(Arc::new));
+        self.shared.generation.fetch_add(1, Ordering::Release);
         self.wake_up_threads();
         old
     }
 
-    pub fn wait_for_change(&mut self) -> impl Deref<Target = Option<Arc<T>>> + '_ {
+    /// Closes the channel for all the clones, waking up every waiting thread.
+    /// Values can still be swapped and read, but waiting on a closed channel returns immediately.
+    pub fn close(&self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.wake_up_threads();
+    }
+
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+
+    #[allow(dead_code)]
+    pub fn wait_for_change(&mut self) -> Result<impl Deref<Target = Option<Arc<T>>> + '_, Closed> {
         let mut guard = self.shared.wait_mutex.lock();
         loop {
-            if Self::get_changed_inner(&mut self.cached, &self.shared.inner) {
-                return &self.cached;
+            if self.is_closed() {
+                return Err(Closed);
+            }
+            if Self::get_changed_inner(&mut self.cached, &mut self.seen_generation, &self.shared) {
+                return Ok(&self.cached);
             }
             guard = self.shared.wait_cv.wait(guard);
         }
     }
+
+    /// Like [`Self::wait_for_change`], but gives up after `timeout` so the caller can check other conditions.
+    pub fn wait_for_change_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<impl Deref<Target = Option<Arc<T>>> + '_, WaitTimeoutError> {
+        let mut guard = self.shared.wait_mutex.lock();
+        loop {
+            if self.is_closed() {
+                return Err(WaitTimeoutError::Closed);
+            }
+            if Self::get_changed_inner(&mut self.cached, &mut self.seen_generation, &self.shared) {
+                return Ok(&self.cached);
+            }
+            let timed_out;
+            (guard, timed_out) = self.shared.wait_cv.wait_timeout(guard, timeout);
+            if timed_out {
+                if self.is_closed() {
+                    return Err(WaitTimeoutError::Closed);
+                }
+                if Self::get_changed_inner(&mut self.cached, &mut self.seen_generation, &self.shared) {
+                    return Ok(&self.cached);
+                }
+                return Err(WaitTimeoutError::Timeout);
+            }
+        }
+    }
 }
 
 mod sync {
     #[cfg(all(feature = "parking_lot", feature = "shuttle"))]
     compile_error!("Can't use sync primitives both from parking_lot and from shuttle");
 
+    use std::time::Duration;
+
     #[cfg(feature = "parking_lot")]
-    use parking_lot::{Condvar as CondvarInternal, Mutex as MutexInternal, MutexGuard};
+    use parking::{Condvar as CondvarInternal, Mutex as MutexInternal, MutexGuard};
 
     #[cfg(feature = "shuttle")]
     use shuttle::sync::{Condvar as CondvarInternal, Mutex as MutexInternal, MutexGuard};
@@ -104,15 +210,18 @@ mod sync {
     use std::sync::{Condvar as CondvarInternal, Mutex as MutexInternal, MutexGuard};
 
     #[cfg(feature = "shuttle")]
-    pub use shuttle::{
-        sync::{
-            atomic::{AtomicBool, AtomicUsize, Ordering},
-            Arc, Barrier,
-        },
-        thread,
+    pub use shuttle::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
     };
+    // Only the shuttle tests spawn threads
+    #[cfg(all(test, feature = "shuttle"))]
+    pub use shuttle::{sync::Barrier, thread};
     #[cfg(not(feature = "shuttle"))]
-    pub use std::sync::Arc;
+    pub use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    };
 
     pub struct Mutex<T>(MutexInternal<T>);
     impl<T> Mutex<T> {
@@ -136,7 +245,7 @@ mod sync {
             Self(CondvarInternal::new())
         }
 
-        #[allow(unused_mut)]
+        #[allow(unused_mut, dead_code)]
         #[inline(always)]
         pub fn wait<'a, T>(&self, mut guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
             #[cfg(not(feature = "parking_lot"))]
@@ -148,6 +257,22 @@ mod sync {
             }
         }
 
+        /// Returns the guard and whether the wait timed out
+        #[allow(unused_mut)]
+        #[inline(always)]
+        pub fn wait_timeout<'a, T>(&self, mut guard: MutexGuard<'a, T>, dur: Duration) -> (MutexGuard<'a, T>, bool) {
+            #[cfg(not(feature = "parking_lot"))]
+            {
+                let (guard, res) = self.0.wait_timeout(guard, dur).unwrap_or_else(|e| e.into_inner());
+                (guard, res.timed_out())
+            }
+            #[cfg(feature = "parking_lot")]
+            {
+                let res = self.0.wait_for(&mut guard, dur);
+                (guard, res.timed_out())
+            }
+        }
+
         #[inline(always)]
         pub fn notify_all(&self) {
             self.0.notify_all();
@@ -157,9 +282,10 @@ mod sync {
 
 #[cfg(test)]
 mod tests {
-    use super::WatchSwap;
     #[cfg(feature = "shuttle")]
-    use crate::sync::{thread, Arc, Barrier};
+    use super::sync::{thread, Arc, Barrier};
+    use super::{Closed, WaitTimeoutError, WatchSwap};
+    use std::time::Duration;
     #[cfg(not(feature = "shuttle"))]
     use std::{
         sync::{Arc, Barrier},
@@ -193,21 +319,41 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_has_changed() {
+        multi_test_runner(
+            || {
+                let (rx, mut tx) = channel("One");
+                assert!(tx.has_changed());
+                assert!(tx.get_changed().is_some());
+                assert!(!tx.has_changed());
+                let tx2 = tx.clone();
+                assert!(!tx2.has_changed());
+                rx.swap("Two");
+                assert!(tx.has_changed() && tx2.has_changed());
+                assert_eq!(tx2.latest().as_deref(), Some(&"Two"));
+                assert_eq!(tx.get_changed().as_deref().map(|a| a.as_deref()), Some(Some(&"Two")));
+                assert!(!tx.has_changed());
+            },
+            false,
+        )
+    }
+
     #[test]
     fn test_sending_val_waiting() {
         multi_test_runner(
             || {
                 let (rx, mut tx) = channel("One");
                 let mut tx2 = tx.clone();
-                assert_eq!(tx.wait_for_change().as_deref().copied(), Some("One"));
+                assert_eq!(tx.wait_for_change().unwrap().as_deref().copied(), Some("One"));
                 assert_eq!(tx.get_changed().as_deref().map(|a| a.as_deref()), None);
                 let mut tx3 = tx.clone();
                 assert_eq!(tx3.get_changed().as_deref().map(|a| a.as_deref()), None);
-                assert_eq!(tx2.wait_for_change().as_deref().copied(), Some("One"));
+                assert_eq!(tx2.wait_for_change().unwrap().as_deref().copied(), Some("One"));
                 rx.swap("Two").unwrap();
-                assert_eq!(tx.wait_for_change().as_deref().copied(), Some("Two"));
-                assert_eq!(tx2.wait_for_change().as_deref().copied(), Some("Two"));
-                assert_eq!(tx3.wait_for_change().as_deref().copied(), Some("Two"));
+                assert_eq!(tx.wait_for_change().unwrap().as_deref().copied(), Some("Two"));
+                assert_eq!(tx2.wait_for_change().unwrap().as_deref().copied(), Some("Two"));
+                assert_eq!(tx3.wait_for_change().unwrap().as_deref().copied(), Some("Two"));
             },
             false,
         )
@@ -242,12 +388,12 @@ mod tests {
                 let barrier_clone: Arc<Barrier> = Arc::clone(&barrier);
                 let handle1 = thread::spawn(move || {
                     barrier_clone.wait();
-                    assert_eq!(tx.wait_for_change().as_deref().copied(), Some("Two"));
+                    assert_eq!(tx.wait_for_change().unwrap().as_deref().copied(), Some("Two"));
                 });
                 let barrier_clone: Arc<Barrier> = Arc::clone(&barrier);
                 let handle2 = thread::spawn(move || {
                     barrier_clone.wait();
-                    assert_eq!(tx2.wait_for_change().as_deref().copied(), Some("Two"));
+                    assert_eq!(tx2.wait_for_change().unwrap().as_deref().copied(), Some("Two"));
                 });
                 barrier.wait();
                 rx.swap("Two");
@@ -258,4 +404,94 @@ mod tests {
             true,
         )
     }
+
+    #[test]
+    fn test_close_wakes_waiting() {
+        multi_test_runner(
+            || {
+                let (rx, mut tx) = channel("One");
+                assert_eq!(tx.wait_for_change().unwrap().as_deref().copied(), Some("One"));
+                let mut tx2 = tx.clone();
+                let handle1 = thread::spawn(move || tx.wait_for_change().map(|v| v.as_deref().copied()));
+                let handle2 = thread::spawn(move || {
+                    tx2.wait_for_change_timeout(Duration::from_secs(60)).map(|v| v.as_deref().copied())
+                });
+                rx.close();
+
+                assert_eq!(handle1.join().unwrap(), Err(Closed));
+                assert_eq!(handle2.join().unwrap(), Err(WaitTimeoutError::Closed));
+                assert!(rx.is_closed());
+            },
+            true,
+        )
+    }
+
+    #[test]
+    fn test_close_racing_swap_and_wait() {
+        multi_test_runner(
+            || {
+                let (rx, mut tx) = channel("One");
+                let rx2 = rx.clone();
+                let mut tx2 = tx.clone();
+                let waiter1 = thread::spawn(move || {
+                    let mut seen = Vec::new();
+                    while let Ok(val) = tx.wait_for_change() {
+                        seen.push(val.as_deref().copied());
+                    }
+                    seen
+                });
+                let waiter2 = thread::spawn(move || {
+                    let mut seen = Vec::new();
+                    loop {
+                        match tx2.wait_for_change_timeout(Duration::from_secs(60)) {
+                            Ok(val) => seen.push(val.as_deref().copied()),
+                            Err(WaitTimeoutError::Timeout) => continue,
+                            Err(WaitTimeoutError::Closed) => return seen,
+                        }
+                    }
+                });
+                let swapper = thread::spawn(move || {
+                    rx2.swap("Two");
+                    rx2.swap("Three");
+                });
+                rx.close();
+                swapper.join().unwrap();
+
+                for seen in [waiter1.join().unwrap(), waiter2.join().unwrap()] {
+                    // Every value can be observed at most once and in order, but closing may cut the sequence short
+                    let expected = [Some("One"), Some("Two"), Some("Three")];
+                    assert!(seen.len() <= expected.len());
+                    assert!(seen
+                        .windows(2)
+                        .all(|w| expected.iter().position(|e| *e == w[0]) < expected.iter().position(|e| *e == w[1])));
+                    assert!(seen.iter().all(|v| expected.contains(v)));
+                }
+                // Swapping after closing is still allowed, but nobody waits for it anymore
+                rx.swap("Four");
+                let mut tx3 = rx.clone();
+                assert_eq!(tx3.wait_for_change().map(|v| v.as_deref().copied()), Err(Closed));
+                assert_eq!(tx3.get_changed().as_deref().map(|a| a.as_deref()), Some(Some(&"Four")));
+            },
+            true,
+        )
+    }
+
+    // Shuttle's `Condvar::wait_timeout` never times out
+    #[cfg(not(feature = "shuttle"))]
+    #[test]
+    fn test_wait_timeout() {
+        let (rx, mut tx) = channel("One");
+        assert_eq!(tx.wait_for_change_timeout(Duration::ZERO).unwrap().as_deref().copied(), Some("One"));
+        assert_eq!(
+            tx.wait_for_change_timeout(Duration::from_millis(10)).map(|v| v.as_deref().copied()),
+            Err(WaitTimeoutError::Timeout)
+        );
+        rx.swap("Two");
+        assert_eq!(tx.wait_for_change_timeout(Duration::from_millis(10)).unwrap().as_deref().copied(), Some("Two"));
+        rx.close();
+        assert_eq!(
+            tx.wait_for_change_timeout(Duration::from_millis(10)).map(|v| v.as_deref().copied()),
+            Err(WaitTimeoutError::Closed)
+        );
+    }
 }
@@ -41,6 +41,12 @@ impl Uint256 {
         Uint256(ret)
     }
 
+    /// Lossy conversion to a float, used for statistics only
+    #[inline]
+    pub fn as_f64(&self) -> f64 {
+        self.0.iter().rev().fold(0.0, |acc, &word| acc * 18446744073709551616.0 + word as f64)
+    }
+
     #[inline(always)]
     pub fn as_bytes(&self) -> [u8; 32] {
         self.to_le_bytes()
@@ -104,3 +110,110 @@ impl core::ops::Shl<usize> for Uint256 {
         Uint256(ret)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{u256_from_compact_target, Uint256};
+    use num_bigint::BigUint;
+    use proptest::prelude::*;
+
+    fn to_big(n: Uint256) -> BigUint {
+        BigUint::from_bytes_le(&n.to_le_bytes())
+    }
+
+    fn from_big(n: &BigUint) -> Uint256 {
+        let mut bytes = [0u8; 32];
+        let le = n.to_bytes_le();
+        bytes[..le.len()].copy_from_slice(&le);
+        Uint256::from_le_bytes(bytes)
+    }
+
+    fn modulus() -> BigUint {
+        BigUint::from(1u8) << 256usize
+    }
+
+    fn any_uint256() -> impl Strategy<Value = Uint256> {
+        // Mix fully random words with sparse ones so equal high words (and therefore lower word comparisons) come up.
+        let word = prop_oneof![any::<u64>(), Just(0u64), Just(u64::MAX), 0u64..4];
+        prop::array::uniform4(word).prop_map(Uint256)
+    }
+
+    fn any_shift() -> impl Strategy<Value = usize> {
+        prop_oneof![Just(0usize), Just(1), Just(63), Just(64), Just(65), Just(128), Just(255), Just(256), 0usize..1024]
+    }
+
+    proptest! {
+        #[test]
+        fn cmp_matches_reference(a in any_uint256(), b in any_uint256()) {
+            prop_assert_eq!(a.cmp(&b), to_big(a).cmp(&to_big(b)));
+            prop_assert_eq!(a.partial_cmp(&b), to_big(a).partial_cmp(&to_big(b)));
+            prop_assert_eq!(a == b, to_big(a) == to_big(b));
+        }
+
+        #[test]
+        fn shl_matches_reference(a in any_uint256(), shift in any_shift()) {
+            let expected = (to_big(a) << shift) % modulus();
+            prop_assert_eq!(to_big(a << shift), expected);
+        }
+
+        #[test]
+        fn bytes_roundtrip(bytes in any::<[u8; 32]>()) {
+            let n = Uint256::from_le_bytes(bytes);
+            prop_assert_eq!(n.to_le_bytes(), bytes);
+            prop_assert_eq!(n.as_bytes(), bytes);
+            prop_assert_eq!(to_big(n), BigUint::from_bytes_le(&bytes));
+            prop_assert_eq!(from_big(&to_big(n)), n);
+        }
+
+        #[test]
+        fn from_u64_matches_reference(n in any::<u64>()) {
+            prop_assert_eq!(to_big(Uint256::from_u64(n)), BigUint::from(n));
+        }
+
+        #[test]
+        fn as_f64_matches_reference(a in any_uint256()) {
+            let expected: f64 = to_big(a).to_string().parse().unwrap();
+            prop_assert!((a.as_f64() - expected).abs() <= expected * 1e-15);
+        }
+
+        #[test]
+        fn lower_hex_is_le_bytes(a in any_uint256()) {
+            prop_assert_eq!(format!("{:x}", a), hex::encode(a.to_le_bytes()));
+        }
+
+        #[test]
+        fn compact_target_matches_reference(bits in any::<u32>()) {
+            // Same quirk as vecnod: small exponents shift the mantissa right *before* the sign bit check.
+            let exponent = bits >> 24;
+            let mut mantissa = BigUint::from(bits & 0x00FF_FFFF);
+            if exponent <= 3 {
+                mantissa >>= 8 * (3 - exponent) as usize;
+            }
+            let expected = if mantissa > BigUint::from(0x7F_FFFFu32) {
+                BigUint::default()
+            } else {
+                (mantissa << (8 * exponent.saturating_sub(3) as usize)) % modulus()
+            };
+            prop_assert_eq!(to_big(u256_from_compact_target(bits)), expected);
+        }
+    }
+
+    #[test]
+    fn shl_edge_cases() {
+        let max = Uint256([u64::MAX; 4]);
+        assert_eq!(max << 0, max);
+        assert_eq!(Uint256::from_u64(1) << 64, Uint256([0, 1, 0, 0]));
+        assert_eq!(Uint256::from_u64(1) << 255, Uint256([0, 0, 0, 1 << 63]));
+        assert_eq!(max << 255, Uint256([0, 0, 0, 1 << 63]));
+        assert_eq!(max << 256, Uint256::default());
+        assert_eq!(max << 1000, Uint256::default());
+    }
+
+    #[test]
+    fn cmp_uses_most_significant_word_first() {
+        let low_heavy = Uint256([u64::MAX, u64::MAX, u64::MAX, 0]);
+        let high_bit = Uint256([0, 0, 0, 1]);
+        assert!(low_heavy < high_bit);
+        assert!(Uint256([1, 0, 0, 5]) > Uint256([u64::MAX, 0, 0, 4]));
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{
+    governor::{Governor, LimitSource},
+    stats::MinerStats,
+    Error,
+};
+use log::{debug, warn};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+const POLL_RATE: Duration = Duration::from_secs(5);
+
+/// The temperature of a thermal zone
+#[derive(Debug, Clone, PartialEq)]
+pub struct Temperature {
+    pub zone: String,
+    pub celsius: f64,
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:.1}°C", self.zone, self.celsius)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ThermalConfig {
+    /// Temperature of the hottest zone above which threads are parked
+    pub max_temp: f64,
+    /// How far below the limit the temperature has to drop before threads are resumed
+    pub hysteresis: f64,
+    pub sys_root: PathBuf,
+}
+
+/// Reads every thermal zone under `sys_root/class/thermal`, zones that can't be read are skipped
+pub fn read_temperatures(sys_root: &Path) -> Result<Vec<Temperature>, Error> {
+    let mut zones = Vec::new();
+    for entry in fs::read_dir(sys_root.join("class/thermal"))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with("thermal_zone") {
+            continue;
+        }
+        // Some zones fail reading when their sensor is powered down
+        let Some(millidegrees) =
+            fs::read_to_string(entry.path().join("temp")).ok().and_then(|temp| temp.trim().parse::<i64>().ok())
+        else {
+            continue;
+        };
+        let zone = fs::read_to_string(entry.path().join("type")).map(|zone| zone.trim().to_owned()).unwrap_or(name);
+        zones.push(Temperature { zone, celsius: millidegrees as f64 / 1000.0 });
+    }
+    zones.sort_by(|a, b| a.zone.cmp(&b.zone));
+    Ok(zones)
+}
+
+/// Steps the active thread count down by one thread per reading while over the limit,
+/// and back up once the temperature dropped below the limit minus the hysteresis.
+#[derive(Debug)]
+pub struct ThermalThrottle {
+    config: ThermalConfig,
+    limit: Option<usize>,
+}
+
+impl ThermalThrottle {
+    pub fn new(config: ThermalConfig) -> Self {
+        Self { config, limit: None }
+    }
+
+    /// Feeds the temperature of the hottest zone and returns the thread limit to apply
+    pub fn update(&mut self, celsius: f64, pool_size: usize) -> Option<usize> {
+        if celsius >= self.config.max_temp {
+            self.limit = Some(self.limit.unwrap_or(pool_size).min(pool_size).saturating_sub(1));
+        } else if celsius <= self.config.max_temp - self.config.hysteresis {
+            self.limit = self.limit.map(|limit| limit + 1).filter(|&limit| limit < pool_size);
+        }
+        self.limit
+    }
+}
+
+/// Polls the thermal zones and limits mining through the governor
+pub async fn watch(config: ThermalConfig, governor: Arc<Governor>, stats: Arc<MinerStats>) {
+    let sys_root = config.sys_root.clone();
+    let mut throttle = ThermalThrottle::new(config);
+    let mut ticker = tokio::time::interval(POLL_RATE);
+    loop {
+        ticker.tick().await;
+        let temperatures = match read_temperatures(&sys_root) {
+            Ok(temperatures) if !temperatures.is_empty() => temperatures,
+            Ok(_) => {
+                warn!("No readable thermal zones in {}, not throttling on temperature", sys_root.display());
+                governor.set_limit(LimitSource::Thermal, None);
+                return;
+            }
+            Err(e) => {
+                warn!("Failed reading temperatures from {}: {}", sys_root.display(), e);
+                continue;
+            }
+        };
+        let hottest = temperatures.iter().map(|temperature| temperature.celsius).fold(f64::MIN, f64::max);
+        debug!("Hottest thermal zone is at {:.1}°C", hottest);
+        let limit = throttle.update(hottest, governor.pool_size());
+        governor.set_limit(LimitSource::Thermal, limit);
+        stats.set_temperatures(temperatures);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_zone(root: &Path, zone: usize, kind: Option<&str>, temp: &str) {
+        let dir = root.join(format!("class/thermal/thermal_zone{}", zone));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("temp"), temp).unwrap();
+        if let Some(kind) = kind {
+            fs::write(dir.join("type"), format!("{}\n", kind)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_read_temperatures() {
+        let root = TempDir::new().unwrap();
+        write_zone(root.path(), 0, Some("x86_pkg_temp"), "72500\n");
+        write_zone(root.path(), 1, None, "41000\n");
+        write_zone(root.path(), 2, Some("broken"), "");
+        fs::create_dir_all(root.path().join("class/thermal/cooling_device0")).unwrap();
+
+        let temperatures = read_temperatures(root.path()).unwrap();
+        assert_eq!(
+            temperatures,
+            vec![
+                Temperature { zone: "thermal_zone1".into(), celsius: 41.0 },
+                Temperature { zone: "x86_pkg_temp".into(), celsius: 72.5 },
+            ]
+        );
+        assert_eq!(temperatures[1].to_string(), "x86_pkg_temp 72.5°C");
+        assert!(read_temperatures(TempDir::new().unwrap().path()).is_err());
+    }
+
+    #[test]
+    fn test_thermal_throttle() {
+        let config = ThermalConfig { max_temp: 80.0, hysteresis: 5.0, sys_root: "/sys".into() };
+        let mut throttle = ThermalThrottle::new(config);
+        assert_eq!(throttle.update(70.0, 4), None);
+        assert_eq!(throttle.update(80.0, 4), Some(3));
+        assert_eq!(throttle.update(82.0, 4), Some(2));
+        // Within the hysteresis band nothing changes
+        assert_eq!(throttle.update(78.0, 4), Some(2));
+        assert_eq!(throttle.update(75.0, 4), Some(3));
+        assert_eq!(throttle.update(74.0, 4), None);
+
+        // Keeps stepping down until mining is paused
+        for expected in (0..4).rev() {
+            assert_eq!(throttle.update(90.0, 4), Some(expected));
+        }
+        assert_eq!(throttle.update(90.0, 4), Some(0));
+        // A pool that shrank in the meantime is respected
+        assert_eq!(throttle.update(60.0, 1), None);
+    }
+}
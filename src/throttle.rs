@@ -0,0 +1,247 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// How long a thread works before the throttle decides how long it should rest
+const WORK_PERIOD: Duration = Duration::from_millis(50);
+/// Longest single sleep, so resting threads still notice shutdowns.
+/// Longer rests are slept off in several sleeps, without mining in between.
+const MAX_REST: Duration = Duration::from_millis(500);
+/// Longest work period the rest is worked out for, 4 regular ones.
+/// A period stretched by a slow batch can't owe more rest than that.
+const MAX_PERIOD: Duration = Duration::from_millis(200);
+
+/// Limits shared by all the mining threads, which can be changed while mining
+#[derive(Debug, Default)]
+pub struct Throttle {
+    // `f64` bits, 0.0 means unlimited
+    max_cpu_fraction: AtomicU64,
+    max_hashrate: AtomicU64,
+    // Per thread cap on top of the split one
+    max_hashrate_per_thread: AtomicU64,
+    num_threads: AtomicUsize,
+}
+
+impl Throttle {
+    pub fn new(max_cpu_percent: Option<f64>, max_hashrate: Option<f64>) -> Self {
+        let throttle = Self::default();
+        throttle.set_max_cpu_percent(max_cpu_percent);
+        throttle.max_hashrate.store(max_hashrate.unwrap_or(0.0).to_bits(), Ordering::Relaxed);
+        throttle
+    }
+
+    pub fn set_max_cpu_percent(&self, max_cpu_percent: Option<f64>) {
+        let fraction = max_cpu_percent.map_or(0.0, |percent| percent / 100.0);
+        self.max_cpu_fraction.store(fraction.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Caps every thread at one hash per `interval`, on top of the total hashrate cap
+    pub fn set_hash_interval(&self, interval: Option<Duration>) {
+        let hashrate =
+            interval.filter(|interval| !interval.is_zero()).map_or(0.0, |interval| 1.0 / interval.as_secs_f64());
+        self.max_hashrate_per_thread.store(hashrate.to_bits(), Ordering::Relaxed);
+    }
+
+    /// The hashrate cap is split evenly between the threads
+    pub fn set_num_threads(&self, num_threads: usize) {
+        self.num_threads.store(num_threads, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn max_cpu_fraction(&self) -> Option<f64> {
+        let fraction = f64::from_bits(self.max_cpu_fraction.load(Ordering::Relaxed));
+        (fraction > 0.0 && fraction < 1.0).then_some(fraction)
+    }
+
+    #[inline]
+    pub fn max_thread_hashrate(&self) -> Option<f64> {
+        let max_hashrate = f64::from_bits(self.max_hashrate.load(Ordering::Relaxed));
+        let num_threads = self.num_threads.load(Ordering::Relaxed).max(1);
+        let split = (max_hashrate > 0.0).then(|| max_hashrate / num_threads as f64);
+        let per_thread = f64::from_bits(self.max_hashrate_per_thread.load(Ordering::Relaxed));
+        match (split, (per_thread > 0.0).then_some(per_thread)) {
+            (Some(split), Some(per_thread)) => Some(split.min(per_thread)),
+            (split, per_thread) => split.or(per_thread),
+        }
+    }
+}
+
+/// How long a thread should rest after working for `worked` and doing `hashes`, to stay within the limits.
+pub fn rest_time(worked: Duration, hashes: u64, max_cpu_fraction: Option<f64>, max_hashrate: Option<f64>) -> Duration {
+    let worked = worked.as_secs_f64();
+    // Working `worked` out of every `worked / fraction` seconds
+    let cpu_rest = max_cpu_fraction.map_or(0.0, |fraction| worked / fraction - worked);
+    // Doing `hashes` should take at least `hashes / max_hashrate` seconds
+    let hashrate_rest = max_hashrate.map_or(0.0, |max_hashrate| hashes as f64 / max_hashrate - worked);
+    Duration::try_from_secs_f64(cpu_rest.max(hashrate_rest).max(0.0)).unwrap_or(Duration::MAX)
+}
+
+/// Per thread controller alternating between work and rest periods according to the [`Throttle`]
+pub struct DutyCycle {
+    throttle: Arc<Throttle>,
+    period_start: Instant,
+    period_hashes: u64,
+}
+
+impl DutyCycle {
+    pub fn new(throttle: Arc<Throttle>) -> Self {
+        Self { throttle, period_start: Instant::now(), period_hashes: 0 }
+    }
+
+    /// Starts a new work period after the thread waited, so the wait counts as rest rather than work
+    pub fn resume(&mut self) {
+        self.period_start = Instant::now();
+        self.period_hashes = 0;
+    }
+
+    /// Called after every batch of hashes, rests at the end of every work period until `stopped` returns true
+    #[inline]
+    pub fn batch_done(&mut self, hashes: u64, stopped: impl Fn() -> bool) {
+        let mut rest = self.rest_after(hashes, Instant::now());
+        if rest.is_zero() {
+            return;
+        }
+        while !rest.is_zero() && !stopped() {
+            let sleep = rest.min(MAX_REST);
+            std::thread::sleep(sleep);
+            rest -= sleep;
+        }
+        self.period_start = Instant::now();
+    }
+
+    fn rest_after(&mut self, hashes: u64, now: Instant) -> Duration {
+        self.period_hashes += hashes;
+        let worked = now.saturating_duration_since(self.period_start);
+        if worked < WORK_PERIOD {
+            return Duration::ZERO;
+        }
+        let max_cpu_fraction = self.throttle.max_cpu_fraction();
+        let max_hashrate = self.throttle.max_thread_hashrate();
+        let mut hashes = std::mem::take(&mut self.period_hashes);
+        self.period_start = now;
+        if max_cpu_fraction.is_none() && max_hashrate.is_none() {
+            return Duration::ZERO;
+        }
+        // Keeps the rates of a long period, but bounds the rest it owes
+        let worked = if worked > MAX_PERIOD {
+            hashes = (hashes as f64 * MAX_PERIOD.as_secs_f64() / worked.as_secs_f64()) as u64;
+            MAX_PERIOD
+        } else {
+            worked
+        };
+        rest_time(worked, hashes, max_cpu_fraction, max_hashrate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn test_rest_time() {
+        assert_eq!(rest_time(100 * MS, 1000, None, None), Duration::ZERO);
+        // 25% CPU means resting 3 times as long as working
+        assert_eq!(rest_time(100 * MS, 1000, Some(0.25), None), 300 * MS);
+        // 1000 hashes at 5000 hash/s should take 200ms
+        assert_eq!(rest_time(100 * MS, 1000, None, Some(5000.0)), 100 * MS);
+        // Already slower than the cap
+        assert_eq!(rest_time(100 * MS, 1000, None, Some(20_000.0)), Duration::ZERO);
+        // The stricter limit wins
+        assert_eq!(rest_time(100 * MS, 1000, Some(0.5), Some(5000.0)), 100 * MS);
+        assert_eq!(rest_time(100 * MS, 1000, Some(0.25), Some(5000.0)), 300 * MS);
+    }
+
+    #[test]
+    fn test_throttle() {
+        let throttle = Throttle::new(None, Some(1000.0));
+        assert_eq!(throttle.max_cpu_fraction(), None);
+        assert_eq!(throttle.max_thread_hashrate(), Some(1000.0));
+        throttle.set_num_threads(4);
+        assert_eq!(throttle.max_thread_hashrate(), Some(250.0));
+        throttle.set_max_cpu_percent(Some(30.0));
+        assert_eq!(throttle.max_cpu_fraction(), Some(0.3));
+        // 100% is the same as no limit
+        throttle.set_max_cpu_percent(Some(100.0));
+        assert_eq!(throttle.max_cpu_fraction(), None);
+        assert_eq!(Throttle::new(None, None).max_thread_hashrate(), None);
+        // The per thread cap applies when it's stricter than the split one
+        throttle.set_hash_interval(Some(10 * MS));
+        assert_eq!(throttle.max_thread_hashrate(), Some(100.0));
+        throttle.set_hash_interval(Some(Duration::ZERO));
+        assert_eq!(throttle.max_thread_hashrate(), Some(250.0));
+    }
+
+    #[test]
+    fn test_duty_cycle() {
+        let throttle = Arc::new(Throttle::new(Some(10.0), None));
+        let mut duty_cycle = DutyCycle::new(throttle);
+        let start = duty_cycle.period_start;
+        // Keeps working until the work period is over
+        assert_eq!(duty_cycle.rest_after(128, start + 10 * MS), Duration::ZERO);
+        // 100ms of work at 10% CPU needs 900ms of rest
+        assert_eq!(duty_cycle.rest_after(128, start + 100 * MS), 900 * MS);
+        duty_cycle.period_start = start + 1000 * MS;
+        assert_eq!(duty_cycle.rest_after(128, start + 1010 * MS), Duration::ZERO);
+        // A period stretched by a slow batch owes no more than `MAX_PERIOD` worth of rest
+        let start = duty_cycle.period_start;
+        assert_eq!(duty_cycle.rest_after(128, start + 10_000 * MS), 1800 * MS);
+
+        let mut unlimited = DutyCycle::new(Arc::new(Throttle::default()));
+        let start = unlimited.period_start;
+        assert_eq!(unlimited.rest_after(128, start + 100 * MS), Duration::ZERO);
+        assert_eq!(unlimited.period_hashes, 0);
+    }
+
+    #[test]
+    fn test_duty_cycle_resume() {
+        let mut duty_cycle = DutyCycle::new(Arc::new(Throttle::new(Some(10.0), None)));
+        let start = duty_cycle.period_start;
+        assert_eq!(duty_cycle.rest_after(128, start + 10 * MS), Duration::ZERO);
+        // Waiting for a template or parked, the thread didn't work
+        std::thread::sleep(2 * WORK_PERIOD);
+        duty_cycle.resume();
+        let start = duty_cycle.period_start;
+        assert_eq!(duty_cycle.rest_after(128, start + 10 * MS), Duration::ZERO);
+        assert_eq!(duty_cycle.period_hashes, 128);
+    }
+
+    /// Mines `batches` batches of 128 hashes taking `batch_time` each, resting like `batch_done`,
+    /// and returns the hashrate and the fraction of the time spent working
+    fn simulate(throttle: Throttle, batch_time: Duration, batches: u32) -> (f64, f64) {
+        let mut duty_cycle = DutyCycle::new(Arc::new(throttle));
+        let start = duty_cycle.period_start;
+        let mut now = start;
+        for _ in 0..batches {
+            now += batch_time;
+            let rest = duty_cycle.rest_after(128, now);
+            if !rest.is_zero() {
+                now += rest;
+                duty_cycle.period_start = now;
+            }
+        }
+        let elapsed = (now - start).as_secs_f64();
+        (f64::from(batches) * 128.0 / elapsed, (batch_time * batches).as_secs_f64() / elapsed)
+    }
+
+    #[test]
+    fn test_duty_cycle_holds_caps() {
+        // Rests much longer than `MAX_REST`, so every one of them takes several sleeps
+        let (hashrate, _) = simulate(Throttle::new(None, Some(100.0)), 64 * MS, 50);
+        assert!((99.0..=101.0).contains(&hashrate), "{}", hashrate);
+        let (_, cpu) = simulate(Throttle::new(Some(5.0), None), 64 * MS, 50);
+        assert!((0.049..=0.051).contains(&cpu), "{}", cpu);
+        // Short batches add up to a work period before resting
+        let (hashrate, cpu) = simulate(Throttle::new(Some(20.0), Some(5000.0)), 5 * MS, 1000);
+        assert!(hashrate <= 5000.0 * 1.01 && cpu <= 0.2 * 1.01, "{} {}", hashrate, cpu);
+        let throttle = Throttle::default();
+        throttle.set_hash_interval(Some(20 * MS));
+        let (hashrate, _) = simulate(throttle, 64 * MS, 50);
+        assert!(hashrate <= 50.0 * 1.01, "{}", hashrate);
+    }
+}